@@ -1,7 +1,9 @@
 mod middlewares;
+mod notification;
 mod utils;
 
 pub use middlewares::*;
+pub use notification::*;
 pub use utils::*;
 
 use chrono::{DateTime, Utc};
@@ -35,6 +37,20 @@ pub struct WorkSpace {
     pub created_at: DateTime<Utc>,
 }
 
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct WorkspaceInvite {
+    pub id: i64,
+    pub ws_id: i64,
+    pub code: String,
+    // only this email can redeem the invite
+    pub email: Option<String>,
+    pub created_by: i64,
+    pub max_uses: i32,
+    pub uses: i32,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, sqlx::Type)]
 #[sqlx(type_name = "chat_type", rename_all = "snake_case")]
 #[serde(rename_all = "snake_case")]
@@ -49,12 +65,81 @@ pub enum ChatType {
 pub struct Chat {
     pub id: i64,
     pub ws_id: i64,
+    pub owner_id: i64,
     pub name: Option<String>,
     pub r#type: ChatType,
     pub members: Vec<i64>,
     pub created_at: DateTime<Utc>,
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "chat_role", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ChatRole {
+    Owner,
+    Admin,
+    Member,
+}
+
+impl ChatRole {
+    // owners and admins manage the chat
+    pub fn is_admin(&self) -> bool {
+        matches!(self, ChatRole::Owner | ChatRole::Admin)
+    }
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct ChatMember {
+    pub chat_id: i64,
+    pub user_id: i64,
+    pub role: ChatRole,
+    pub joined_at: DateTime<Utc>,
+    pub muted: bool,
+}
+
+// either a single access token (jti) or every token of a signin session
+// (sid). Kept until expires_at, when the tokens it applies to have expired
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct TokenRevocation {
+    pub id: i64,
+    pub jti: Option<String>,
+    pub sid: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Reaction {
+    pub message_id: i64,
+    pub user_id: i64,
+    pub emoji: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct ChatRead {
+    pub chat_id: i64,
+    pub user_id: i64,
+    pub last_read_message_id: i64,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "presence_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceStatus {
+    Online,
+    Away,
+    Offline,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Presence {
+    pub user_id: i64,
+    pub status: PresenceStatus,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
 pub struct Message {
     pub id: i64,
@@ -63,6 +148,10 @@ pub struct Message {
     pub content: String,
     pub files: Vec<String>,
     pub created_at: DateTime<Utc>,
+    pub edited_at: Option<DateTime<Utc>>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub reply_to: Option<i64>,
+    pub thread_root_id: Option<i64>,
 }
 
 #[cfg(test)]
@@ -48,9 +48,10 @@ where
         };
 
     let req = match state.verify(&token) {
-        Ok(user) => {
+        Ok((user, session)) => {
             let mut req = Request::from_parts(parts, body);
             req.extensions_mut().insert(user);
+            req.extensions_mut().insert(session);
             req
         }
         Err(e) => {
@@ -67,7 +68,7 @@ where
 mod tests {
     use std::sync::Arc;
 
-    use crate::{DecodingKey, EncodingKey, User};
+    use crate::{DecodingKey, EncodingKey, TokenSession, User};
 
     use super::*;
     use anyhow::Result;
@@ -84,7 +85,7 @@ mod tests {
 
     impl TokenVeirfy for AppState {
         type Err = ();
-        fn verify(&self, token: &str) -> Result<User, Self::Err> {
+        fn verify(&self, token: &str) -> Result<(User, TokenSession), Self::Err> {
             self.0.dk.verify(token).map_err(|_| ())
         }
     }
@@ -101,7 +102,7 @@ mod tests {
         let dk = DecodingKey::load(decoding_pem)?;
         let state = AppState(Arc::new(AppStateInner { ek, dk }));
         let user = User::new(1, "hp", "hp@gmail.com");
-        let token = state.0.ek.sign(user)?;
+        let token = state.0.ek.sign(user, "session")?;
 
         let app = Router::new()
             .route("/", get(handler))
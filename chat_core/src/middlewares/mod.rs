@@ -14,7 +14,7 @@ mod auth;
 mod request_id;
 mod server_time;
 
-use crate::User;
+use crate::{TokenSession, User};
 
 use self::request_id::set_request_id;
 
@@ -23,10 +23,12 @@ const SERVER_TIME_HEADER: &str = "x-server-time";
 
 pub use auth::verriy_token;
 
+// implementations reject revoked tokens, the session is kept in the request
+// extensions next to the user
 pub trait TokenVeirfy {
     type Err: fmt::Debug;
 
-    fn verify(&self, token: &str) -> Result<User, Self::Err>;
+    fn verify(&self, token: &str) -> Result<(User, TokenSession), Self::Err>;
 }
 pub fn set_layer(app: Router) -> Router {
     app.layer(
@@ -0,0 +1,182 @@
+use anyhow::bail;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{Chat, ChatRead, Message, Reaction, TokenRevocation};
+
+// bump this whenever the payload built by the database triggers changes
+pub const NOTIFICATION_VERSION: u32 = 2;
+
+pub const CHAT_UPDATED: &str = "chat_updated";
+pub const CHAT_MESSAGE_CREATED: &str = "chat_message_created";
+pub const CHAT_MESSAGE_UPDATED: &str = "chat_message_updated";
+pub const CHAT_MESSAGE_DELETED: &str = "chat_message_deleted";
+pub const CHAT_TYPING: &str = "chat_typing";
+pub const CHAT_READ: &str = "chat_read";
+pub const MESSAGE_REACTION_CHANGED: &str = "message_reaction_changed";
+pub const TOKEN_REVOKED: &str = "token_revoked";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ChatOp {
+    Insert,
+    Update,
+    Delete,
+}
+
+// payload of `chat_updated`, sent by add_to_chat and add_to_chat_members
+// triggers. `added`/`removed` are the chat_members rows changed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatUpdated {
+    pub version: u32,
+    pub op: ChatOp,
+    pub old: Option<Chat>,
+    pub new: Option<Chat>,
+    #[serde(default)]
+    pub added: Vec<i64>,
+    #[serde(default)]
+    pub removed: Vec<i64>,
+}
+
+// payload of `chat_message_created`, sent by add_to_message trigger
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessageCreated {
+    pub version: u32,
+    pub message: Message,
+    pub members: Vec<i64>,
+    // lets clients route a reply to an open thread panel
+    pub thread_root_id: Option<i64>,
+}
+
+// payload of `chat_message_updated` and `chat_message_deleted`, sent by
+// add_to_message trigger when the content changes or the message is deleted
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessageUpdated {
+    pub version: u32,
+    pub message: Message,
+    pub members: Vec<i64>,
+}
+
+// payload of `message_reaction_changed`, sent by add_to_message_reaction
+// trigger. `op` is either INSERT or DELETE
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MessageReactionChanged {
+    pub version: u32,
+    pub op: ChatOp,
+    pub chat_id: i64,
+    pub reaction: Reaction,
+    pub members: Vec<i64>,
+}
+
+// payload of `chat_read`, sent by add_to_chat_read trigger
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatReadUpdated {
+    pub version: u32,
+    pub read: ChatRead,
+    pub members: Vec<i64>,
+}
+
+// payload of `token_revoked`, sent by add_to_token_revocation trigger
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenRevoked {
+    pub version: u32,
+    pub revocation: TokenRevocation,
+}
+
+// payload of `chat_typing`, sent by chat_server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatTyping {
+    pub version: u32,
+    pub chat_id: i64,
+    pub user_id: i64,
+    pub members: Vec<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PayloadHeader {
+    version: Option<u32>,
+}
+
+// parse a pg_notify payload, rejecting payloads from another version of the triggers
+pub fn parse_notification<T: DeserializeOwned>(payload: &str) -> anyhow::Result<T> {
+    let header: PayloadHeader = serde_json::from_str(payload)?;
+    match header.version {
+        Some(NOTIFICATION_VERSION) => Ok(serde_json::from_str(payload)?),
+        v => bail!("unsupported notification version: {:?}", v),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChatType;
+    use anyhow::Result;
+    use chrono::Utc;
+
+    #[test]
+    fn chat_message_created_should_round_trip() -> Result<()> {
+        let payload = ChatMessageCreated {
+            version: NOTIFICATION_VERSION,
+            message: Message {
+                id: 1,
+                chat_id: 1,
+                sender_id: 1,
+                content: "hello".to_string(),
+                files: vec![],
+                created_at: Utc::now(),
+                edited_at: None,
+                deleted_at: None,
+                reply_to: None,
+                thread_root_id: None,
+            },
+            members: vec![1, 2],
+            thread_root_id: None,
+        };
+        let s = serde_json::to_string(&payload)?;
+        let ret: ChatMessageCreated = parse_notification(&s)?;
+        assert_eq!(ret, payload);
+        Ok(())
+    }
+
+    #[test]
+    fn chat_updated_should_round_trip() -> Result<()> {
+        let chat = Chat {
+            id: 1,
+            ws_id: 1,
+            owner_id: 1,
+            name: None,
+            r#type: ChatType::Single,
+            members: vec![1, 2],
+            created_at: Utc::now(),
+        };
+        let payload = ChatUpdated {
+            version: NOTIFICATION_VERSION,
+            op: ChatOp::Delete,
+            old: Some(chat),
+            new: None,
+            added: vec![],
+            removed: vec![1, 2],
+        };
+        let s = serde_json::to_string(&payload)?;
+        let ret: ChatUpdated = parse_notification(&s)?;
+        assert_eq!(ret, payload);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_trigger_payload_should_work() -> Result<()> {
+        // same shape as json_build_object in add_to_message trigger
+        let s = r#"{"version" : 2, "message" : {"id":11,"chat_id":1,"sender_id":1,"content":"hello","files":[],"created_at":"2024-08-20T15:00:00.123456+00:00"}, "members" : [1,2,3]}"#;
+        let ret: ChatMessageCreated = parse_notification(s)?;
+        assert_eq!(ret.message.id, 11);
+        assert_eq!(ret.members, vec![1, 2, 3]);
+
+        // payload of an older version is rejected
+        let s = s.replace(r#""version" : 2"#, r#""version" : 1"#);
+        assert!(parse_notification::<ChatMessageCreated>(&s).is_err());
+
+        // payload without version is rejected
+        let s = r#"{"message" : {"id":11,"chat_id":1,"sender_id":1,"content":"hello","files":[],"created_at":"2024-08-20T15:00:00.123456+00:00"}, "members" : [1,2,3]}"#;
+        assert!(parse_notification::<ChatMessageCreated>(s).is_err());
+        Ok(())
+    }
+}
@@ -1,8 +1,10 @@
 use crate::User;
 use jwt_simple::prelude::*;
 use std::ops::Deref;
+use uuid::Uuid;
 
-const JWT_DUTARION: u64 = 60 * 60 * 24 * 7;
+// access tokens are short-lived, clients renew them with a refresh token
+pub const JWT_DUTARION: u64 = 60 * 15;
 const JWT_ISSUER: &str = "chat_server";
 const JWT_AUDIENCE: &str = "chat_web";
 
@@ -10,15 +12,36 @@ pub struct EncodingKey(Ed25519KeyPair);
 
 pub struct DecodingKey(Ed25519PublicKey);
 
+#[derive(Debug, Serialize, Deserialize)]
+struct SessionClaims {
+    #[serde(flatten)]
+    user: User,
+    sid: String,
+}
+
+// identifies an access token and the signin session it was issued for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenSession {
+    pub jti: String,
+    pub sid: String,
+}
+
 impl EncodingKey {
     pub fn load(pem: &str) -> Result<EncodingKey, jwt_simple::Error> {
         Ok(Self(Ed25519KeyPair::from_pem(pem)?))
     }
 
-    pub fn sign(&self, user: impl Into<User>) -> Result<String, jwt_simple::Error> {
-        let claims = Claims::with_custom_claims(user.into(), Duration::from_secs(JWT_DUTARION));
-        let claim = claims.with_issuer(JWT_ISSUER).with_audience(JWT_AUDIENCE);
-        Ok(self.0.sign(claim)?)
+    pub fn sign(&self, user: impl Into<User>, sid: &str) -> Result<String, jwt_simple::Error> {
+        let custom = SessionClaims {
+            user: user.into(),
+            sid: sid.to_string(),
+        };
+        let claims = Claims::with_custom_claims(custom, Duration::from_secs(JWT_DUTARION));
+        let claim = claims
+            .with_issuer(JWT_ISSUER)
+            .with_audience(JWT_AUDIENCE)
+            .with_jwt_id(Uuid::now_v7());
+        self.0.sign(claim)
     }
 }
 
@@ -27,23 +50,25 @@ impl DecodingKey {
         Ok(Self(Ed25519PublicKey::from_pem(pem)?))
     }
 
-    #[allow(unused)]
-    pub fn verify(&self, token: &str) -> Result<User, jwt_simple::Error> {
-        let mut options = VerificationOptions::default();
-        options.allowed_issuers = Some(HashSet::from_strings(&[JWT_ISSUER]));
-        options.allowed_audiences = Some(HashSet::from_strings(&[JWT_AUDIENCE]));
-
-        let claims = self.0.verify_token::<User>(token, Some(options))?;
-        Ok(claims.custom)
+    pub fn verify(&self, token: &str) -> Result<(User, TokenSession), jwt_simple::Error> {
+        let options = VerificationOptions {
+            allowed_issuers: Some(HashSet::from_strings(&[JWT_ISSUER])),
+            allowed_audiences: Some(HashSet::from_strings(&[JWT_AUDIENCE])),
+            ..Default::default()
+        };
+
+        let claims = self.0.verify_token::<SessionClaims>(token, Some(options))?;
+        let Some(jti) = claims.jwt_id else {
+            return Err(jwt_simple::Error::msg("token has no jti"));
+        };
+        let session = TokenSession {
+            jti,
+            sid: claims.custom.sid,
+        };
+        Ok((claims.custom.user, session))
     }
 }
 
-#[allow(unused)]
-pub fn generate_token(user: User, key: &EncodingKey) -> Result<String, jwt_simple::Error> {
-    let claims = Claims::with_custom_claims(user, Duration::from_secs(JWT_DUTARION));
-    Ok(key.0.sign(claims)?)
-}
-
 impl Deref for EncodingKey {
     type Target = Ed25519KeyPair;
 
@@ -73,11 +98,15 @@ mod tests {
         let dk = DecodingKey::load(decoding_pem)?;
 
         let user = User::new(1, "HP", "HP@example.com");
-        let token = ek.sign(user.clone())?;
+        let token = ek.sign(user.clone(), "session")?;
 
-        let user2 = dk.verify(&token)?;
+        let (user2, session) = dk.verify(&token)?;
 
         assert_eq!(user, user2);
+        assert_eq!(session.sid, "session");
+        // every token has its own id
+        let (_, session2) = dk.verify(&ek.sign(user, "session")?)?;
+        assert_ne!(session.jti, session2.jti);
         Ok(())
     }
 }
@@ -1,3 +1,5 @@
 mod jwt;
+mod revocation;
 
-pub use jwt::{DecodingKey, EncodingKey};
+pub use jwt::{DecodingKey, EncodingKey, TokenSession, JWT_DUTARION};
+pub use revocation::RevocationList;
@@ -0,0 +1,177 @@
+use crate::{parse_notification, TokenRevocation, TokenRevoked, TokenSession};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+use tokio::sync::watch;
+
+// revocations of access tokens that haven't expired yet. Every server keeps a
+// copy, reloaded when its listener connects and updated on `token_revoked`
+#[derive(Debug, Clone)]
+pub struct RevocationList {
+    inner: Arc<RwLock<Revocations>>,
+    // bumped on every change, long-lived connections recheck their token
+    changed: watch::Sender<u64>,
+}
+
+#[derive(Debug, Default)]
+struct Revocations {
+    // jti/sid -> expires_at
+    jtis: HashMap<String, DateTime<Utc>>,
+    sids: HashMap<String, DateTime<Utc>>,
+}
+
+impl RevocationList {
+    pub fn insert(&self, revocation: &TokenRevocation) {
+        {
+            let mut inner = self.inner.write().expect("revocation list poisoned");
+            inner.prune(Utc::now());
+            inner.insert(revocation);
+        }
+        self.changed.send_modify(|v| *v += 1);
+    }
+
+    // a `token_revoked` notification
+    pub fn apply(&self, payload: &str) -> anyhow::Result<()> {
+        let notification: TokenRevoked = parse_notification(payload)?;
+        self.insert(&notification.revocation);
+        Ok(())
+    }
+
+    pub fn replace(&self, revocations: impl IntoIterator<Item = TokenRevocation>) {
+        let mut list = Revocations::default();
+        for revocation in revocations {
+            list.insert(&revocation);
+        }
+        *self.inner.write().expect("revocation list poisoned") = list;
+        self.changed.send_modify(|v| *v += 1);
+    }
+
+    pub fn is_revoked(&self, session: &TokenSession) -> bool {
+        let inner = self.inner.read().expect("revocation list poisoned");
+        inner.jtis.contains_key(&session.jti) || inner.sids.contains_key(&session.sid)
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.changed.subscribe()
+    }
+
+    // resolves once the session is revoked, for connections outliving the request
+    pub async fn revoked(&self, session: &TokenSession) {
+        let mut rx = self.subscribe();
+        while !self.is_revoked(session) {
+            if rx.changed().await.is_err() {
+                // the list is gone, so is the server
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+
+    pub async fn load(&self, pool: &PgPool) -> Result<usize, sqlx::Error> {
+        let revocations: Vec<TokenRevocation> = sqlx::query_as(
+            r#"
+            SELECT id, jti, sid, created_at, expires_at
+            FROM token_revocations
+            WHERE expires_at > now()
+            "#,
+        )
+        .fetch_all(pool)
+        .await?;
+        let len = revocations.len();
+        self.replace(revocations);
+        Ok(len)
+    }
+}
+
+impl Default for RevocationList {
+    fn default() -> Self {
+        Self {
+            inner: Default::default(),
+            changed: watch::channel(0).0,
+        }
+    }
+}
+
+impl Revocations {
+    fn insert(&mut self, revocation: &TokenRevocation) {
+        let expires_at = revocation.expires_at;
+        if let Some(jti) = &revocation.jti {
+            self.jtis.insert(jti.clone(), expires_at);
+        }
+        if let Some(sid) = &revocation.sid {
+            self.sids.insert(sid.clone(), expires_at);
+        }
+    }
+
+    fn prune(&mut self, now: DateTime<Utc>) {
+        self.jtis.retain(|_, expires_at| *expires_at > now);
+        self.sids.retain(|_, expires_at| *expires_at > now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn revocation(jti: Option<&str>, sid: Option<&str>) -> TokenRevocation {
+        TokenRevocation {
+            id: 1,
+            jti: jti.map(|v| v.to_string()),
+            sid: sid.map(|v| v.to_string()),
+            created_at: Utc::now(),
+            expires_at: Utc::now() + Duration::minutes(15),
+        }
+    }
+
+    #[tokio::test]
+    async fn revocation_list_should_work() {
+        let list = RevocationList::default();
+        let rx = list.subscribe();
+        let session = TokenSession {
+            jti: "a".to_string(),
+            sid: "s1".to_string(),
+        };
+        assert!(!list.is_revoked(&session));
+
+        list.insert(&revocation(None, Some("s1")));
+        assert!(list.is_revoked(&session));
+        assert!(rx.has_changed().unwrap());
+        // already revoked, doesn't wait
+        list.revoked(&session).await;
+
+        list.replace(vec![revocation(Some("a"), None)]);
+        assert!(list.is_revoked(&session));
+        let other = TokenSession {
+            jti: "b".to_string(),
+            ..session.clone()
+        };
+        assert!(!list.is_revoked(&other));
+
+        // expired revocations are dropped
+        let mut expired = revocation(Some("b"), None);
+        expired.expires_at = Utc::now();
+        list.replace(vec![expired]);
+        list.insert(&revocation(Some("c"), None));
+        assert!(!list.is_revoked(&other));
+
+        let waiter = {
+            let list = list.clone();
+            let other = other.clone();
+            tokio::spawn(async move { list.revoked(&other).await })
+        };
+        list.insert(&revocation(Some("b"), None));
+        waiter.await.unwrap();
+
+        // same shape as json_build_object in add_to_token_revocation trigger
+        let payload = r#"{"version" : 2, "revocation" : {"id":2,"jti":null,"sid":"s2","created_at":"2024-09-03T10:00:00.123456+00:00","expires_at":"2099-01-01T00:00:00+00:00"}}"#;
+        list.apply(payload).unwrap();
+        let s2 = TokenSession {
+            sid: "s2".to_string(),
+            ..other
+        };
+        assert!(list.is_revoked(&s2));
+    }
+}
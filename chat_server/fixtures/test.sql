@@ -12,16 +12,24 @@ INSERT INTO users(ws_id, email, fullname, password_hash)
 (1, 'charlie@acme.org', 'Charlie Chen', '$argon2id$v=19$m=19456,t=2,p=1$MxGhY+ib/kplwBPLa7u2ug$c5h9u7Sc8Px8J5+qgNdOjSY7ZJO2QN4rugKpapGW4XU'),
 (1, 'daisy@acme.org', 'Daisy Chen', '$argon2id$v=19$m=19456,t=2,p=1$MxGhY+ib/kplwBPLa7u2ug$c5h9u7Sc8Px8J5+qgNdOjSY7ZJO2QN4rugKpapGW4XU');
 
+INSERT INTO workspace_members(ws_id, user_id)
+  VALUES (1, 1), (1, 2), (1, 3), (1, 4), (1, 5);
 
 -- insert 4 chats
 -- insert public/private channel
-INSERT INTO chats (ws_id, name, type, members)
-    VALUES (1, 'general', 'public_channel', '{1,2,3,4,5}'),
-     (1, 'general', 'private_channel', '{1,2,3}');
+INSERT INTO chats (ws_id, owner_id, name, type)
+    VALUES (1, 1, 'general', 'public_channel'),
+     (1, 1, 'general', 'private_channel');
 
-INSERT INTO  chats(ws_id, type, members)
-    VALUES (1, 'single', '{1, 2}'),
-    (1, 'group', '{1, 3}');
+INSERT INTO  chats(ws_id, owner_id, type)
+    VALUES (1, 1, 'single'),
+    (1, 1, 'group');
+
+INSERT INTO chat_members(chat_id, user_id, role)
+    VALUES (1, 1, 'owner'), (1, 2, 'member'), (1, 3, 'member'), (1, 4, 'member'), (1, 5, 'member'),
+    (2, 1, 'owner'), (2, 2, 'member'), (2, 3, 'member'),
+    (3, 1, 'owner'), (3, 2, 'member'),
+    (4, 1, 'owner'), (4, 3, 'member');
 
 
 -- insert messages
@@ -8,21 +8,57 @@ pub enum AppError {
     #[error("email already exists: {0}")]
     EmailAlreadyExists(String),
 
+    #[error("workspace already exists: {0}")]
+    WorkspaceAlreadyExists(String),
+
+    #[error("create user error: {0}")]
+    CreateUserError(String),
+
+    #[error("update workspace error: {0}")]
+    UpdateWorkspaceError(String),
+
     #[error("create chat error: {0}")]
     CreateChatError(String),
 
+    #[error("update chat error: {0}")]
+    UpdateChatError(String),
+
+    #[error("list chat error: {0}")]
+    ListChatError(String),
+
     #[error("{0}")]
     ChatFileError(String),
 
     #[error("create message error: {0}")]
     CreateMessageError(String),
 
+    #[error("update message error: {0}")]
+    UpdateMessageError(String),
+
+    #[error("list message error: {0}")]
+    ListMessageError(String),
+
+    #[error("reaction error: {0}")]
+    ReactionError(String),
+
+    #[error("search error: {0}")]
+    SearchError(String),
+
+    #[error("invite error: {0}")]
+    InviteError(String),
+
     #[error("io found: {0}")]
     IoError(#[from] std::io::Error),
 
     #[error("not found: {0}")]
     NotFound(String),
 
+    #[error("refresh token error: {0}")]
+    RefreshTokenError(String),
+
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+
     #[error("sql error: {0}")]
     SqlxError(#[from] sqlx::Error),
 
@@ -32,6 +68,9 @@ pub enum AppError {
     #[error("jwt error: {0}")]
     JwtError(#[from] jwt_simple::Error),
 
+    #[error("token revoked: {0}")]
+    TokenRevoked(String),
+
     #[error("http header parse error: {0}")]
     HttpHeaderError(#[from] axum::http::header::InvalidHeaderValue),
 }
@@ -42,12 +81,25 @@ impl IntoResponse for AppError {
             Self::SqlxError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::PasswordHasherError(_) => StatusCode::UNPROCESSABLE_ENTITY,
             Self::JwtError(_) => StatusCode::FORBIDDEN,
+            Self::TokenRevoked(_) => StatusCode::FORBIDDEN,
             Self::HttpHeaderError(_) => StatusCode::UNPROCESSABLE_ENTITY,
             Self::EmailAlreadyExists(_) => StatusCode::CONFLICT,
+            Self::WorkspaceAlreadyExists(_) => StatusCode::CONFLICT,
+            Self::CreateUserError(_) => StatusCode::BAD_REQUEST,
+            Self::UpdateWorkspaceError(_) => StatusCode::BAD_REQUEST,
             Self::CreateChatError(_) => StatusCode::BAD_REQUEST,
+            Self::UpdateChatError(_) => StatusCode::BAD_REQUEST,
+            Self::ListChatError(_) => StatusCode::BAD_REQUEST,
             Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::PermissionDenied(_) => StatusCode::FORBIDDEN,
+            Self::RefreshTokenError(_) => StatusCode::UNAUTHORIZED,
             Self::IoError(_) => StatusCode::CONFLICT,
             Self::CreateMessageError(_) => StatusCode::BAD_REQUEST,
+            Self::UpdateMessageError(_) => StatusCode::BAD_REQUEST,
+            Self::ListMessageError(_) => StatusCode::BAD_REQUEST,
+            Self::ReactionError(_) => StatusCode::BAD_REQUEST,
+            Self::SearchError(_) => StatusCode::BAD_REQUEST,
+            Self::InviteError(_) => StatusCode::BAD_REQUEST,
             Self::ChatFileError(_) => StatusCode::BAD_REQUEST,
         };
 
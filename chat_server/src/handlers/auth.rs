@@ -1,15 +1,38 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use chat_core::{TokenSession, User};
 use serde::{Deserialize, Serialize};
 use tracing::info;
 
 use crate::{
-    models::{CreateUser, SigninUser},
+    models::{CreateUser, RefreshInput, SigninUser},
     AppError, AppState,
 };
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthOutput {
     token: String,
+    refresh_token: String,
+}
+
+impl AuthOutput {
+    // an access token and a refresh token starting a new family
+    async fn issue(state: &AppState, user: User) -> Result<Self, AppError> {
+        let refresh_token = state.create_refresh_token(&user).await?;
+        let token = state.ek.sign(user, &refresh_token.sid)?;
+        Ok(Self {
+            token,
+            refresh_token: refresh_token.token,
+        })
+    }
 }
 
 pub(crate) async fn signup_handler(
@@ -18,10 +41,9 @@ pub(crate) async fn signup_handler(
 ) -> Result<impl IntoResponse, AppError> {
     info!("Entering signup_handler");
     let user = state.create_user(&input).await?;
-    let token = state.ek.sign(user)?;
     // let mut header = HeaderMap::new();
     // header.insert("X-Token", HeaderValue::from_str(&token)?);
-    let body = Json(AuthOutput { token });
+    let body = Json(AuthOutput::issue(&state, user).await?);
     Ok((StatusCode::CREATED, body))
 }
 
@@ -32,23 +54,86 @@ pub(crate) async fn signin_handler(
     let user = state.verify_user(&input).await?;
     match user {
         Some(user) => {
-            let token = state.ek.sign(user)?;
-            Ok((StatusCode::OK, Json(AuthOutput { token })).into_response())
+            let body = Json(AuthOutput::issue(&state, user).await?);
+            Ok((StatusCode::OK, body).into_response())
         }
         None => Ok((StatusCode::FORBIDDEN, "Invalid email or password").into_response()),
     }
 }
 
+// a token for the same user, scoped to another of their workspaces
+pub(crate) async fn switch_workspace_handler(
+    Extension(mut user): Extension<User>,
+    State(state): State<AppState>,
+    Path(ws_id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    if !state.is_workspace_member(ws_id, user.id as _).await? {
+        return Err(AppError::PermissionDenied(format!(
+            "User {} is not a member of workspace {ws_id}",
+            user.id
+        )));
+    }
+    user.ws_id = ws_id as _;
+    Ok(Json(AuthOutput::issue(&state, user).await?))
+}
+
+pub(crate) async fn refresh_handler(
+    State(state): State<AppState>,
+    Json(input): Json<RefreshInput>,
+) -> Result<impl IntoResponse, AppError> {
+    let (user, refresh_token) = state.rotate_refresh_token(&input.refresh_token).await?;
+    let token = state.ek.sign(user, &refresh_token.sid)?;
+    Ok(Json(AuthOutput {
+        token,
+        refresh_token: refresh_token.token,
+    }))
+}
+
+// the access token presented along is revoked too, it may belong to another
+// session. Expired or invalid ones are ignored
+pub(crate) async fn logout_handler(
+    State(state): State<AppState>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    Json(input): Json<RefreshInput>,
+) -> Result<impl IntoResponse, AppError> {
+    state.revoke_refresh_token(&input.refresh_token).await?;
+    if let Some(TypedHeader(Authorization(bearer))) = bearer {
+        if let Ok((_, session)) = state.dk.verify(bearer.token()) {
+            state.revoke_access_token(&session.jti).await?;
+        }
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// only the access token making the request, its session can still be refreshed
+pub(crate) async fn revoke_token_handler(
+    Extension(session): Extension<TokenSession>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    state.revoke_access_token(&session.jti).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// sign out everywhere, including the session making the request
+pub(crate) async fn revoke_sessions_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    state.revoke_user_sessions(user.id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use anyhow::{Ok, Result};
+    use chat_core::TokenVeirfy;
     use http_body_util::BodyExt;
 
     #[tokio::test]
     async fn signup_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
-        let input = CreateUser::new("none", "HP", "HP@email.com", "123456");
+        let input = CreateUser::new("hp", "HP", "HP@email.com", "123456");
         let ret = signup_handler(State(state), Json(input))
             .await?
             .into_response();
@@ -58,6 +143,130 @@ mod tests {
         let ret: AuthOutput = serde_json::from_slice(&body)?;
 
         assert_ne!(ret.token, "");
+        assert_ne!(ret.refresh_token, "");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refresh_and_logout_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = SigninUser::new("hp@acme.org", "123456");
+        let ret = signin_handler(State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        let body = ret.into_body().collect().await?.to_bytes();
+        let output: AuthOutput = serde_json::from_slice(&body)?;
+
+        let input = RefreshInput {
+            refresh_token: output.refresh_token,
+        };
+        let ret = refresh_handler(State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let output: AuthOutput = serde_json::from_slice(&body)?;
+        assert_eq!(state.verify(&output.token)?.0.id, 1);
+
+        let input = RefreshInput {
+            refresh_token: output.refresh_token,
+        };
+        // the presented access token of another session is revoked as well
+        let other = AuthOutput::issue(&state, state.find_user_by_id(1).await?.unwrap()).await?;
+        let bearer = TypedHeader(Authorization::bearer(&other.token)?);
+        let ret = logout_handler(State(state.clone()), Some(bearer), Json(input.clone()))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+        // the access token of the session is revoked too
+        let err = state.verify(&output.token).unwrap_err();
+        assert!(matches!(err, AppError::TokenRevoked(_)));
+        let err = state.verify(&other.token).unwrap_err();
+        assert!(matches!(err, AppError::TokenRevoked(_)));
+        state.rotate_refresh_token(&other.refresh_token).await?;
+
+        let ret = refresh_handler(State(state), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn revoke_sessions_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        let first = AuthOutput::issue(&state, user.clone()).await?;
+        let second = AuthOutput::issue(&state, user.clone()).await?;
+        let other = state.find_user_by_id(2).await?.unwrap();
+        let other = AuthOutput::issue(&state, other).await?;
+
+        let ret = revoke_sessions_handler(Extension(user.clone()), State(state.clone()))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+        for output in [&first, &second] {
+            let err = state.verify(&output.token).unwrap_err();
+            assert!(matches!(err, AppError::TokenRevoked(_)));
+            let err = state
+                .rotate_refresh_token(&output.refresh_token)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, AppError::RefreshTokenError(_)));
+        }
+        state.verify(&other.token)?;
+
+        // a new signin isn't affected
+        let output = AuthOutput::issue(&state, user).await?;
+        state.verify(&output.token)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn revoke_token_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        let output = AuthOutput::issue(&state, user).await?;
+        let (_, session) = state.verify(&output.token)?;
+
+        let ret = revoke_token_handler(Extension(session), State(state.clone()))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+        let err = state.verify(&output.token).unwrap_err();
+        assert!(matches!(err, AppError::TokenRevoked(_)));
+
+        // the session gets a new access token on refresh
+        let (user, next) = state.rotate_refresh_token(&output.refresh_token).await?;
+        state.verify(&state.ek.sign(user, &next.sid)?)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn switch_workspace_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateUser::new("hp", "HP", "HP@email.com", "123456");
+        let user = state.create_user(&input).await?;
+        sqlx::query("INSERT INTO workspace_members (ws_id, user_id) VALUES (1, $1)")
+            .bind(user.id)
+            .execute(&state.pool)
+            .await?;
+
+        let ret = switch_workspace_handler(Extension(user.clone()), State(state.clone()), Path(1))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let ret: AuthOutput = serde_json::from_slice(&body)?;
+        let (scoped, _) = state.dk.verify(&ret.token)?;
+        assert_eq!(scoped.id, user.id);
+        assert_eq!(scoped.ws_id, 1);
+
+        let err = switch_workspace_handler(Extension(user), State(state), Path(2))
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
         Ok(())
     }
 
@@ -67,7 +276,7 @@ mod tests {
         let name = "HP";
         let email = "hp@gmail.com";
         let password = "123456";
-        let user = CreateUser::new("none", name, email, password);
+        let user = CreateUser::new("hp", name, email, password);
         state.create_user(&user).await?;
         let input = SigninUser::new(email, password);
         let ret = signin_handler(State(state), Json(input))
@@ -1,7 +1,7 @@
-use crate::{AppError, AppState, CreateChat};
+use crate::{AppError, AppState, CreateChat, ListChat, MarkRead, UpdateChat, UpdateMemberRole};
 use axum::http::StatusCode;
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     response::IntoResponse,
     Extension, Json,
 };
@@ -11,18 +11,49 @@ use tracing::info;
 pub(crate) async fn list_chat_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
+    Query(input): Query<ListChat>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chat = state.fetch_chats(user.ws_id as _).await?;
+    let chat = state
+        .fetch_chats(input, user.ws_id as _, user.id as _)
+        .await?;
     info!("user: {:?}", user);
     Ok((StatusCode::OK, Json(chat)).into_response())
 }
 
+pub(crate) async fn list_channel_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let channels = state.fetch_channels(user.ws_id as _).await?;
+    Ok(Json(channels))
+}
+
+pub(crate) async fn join_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state.join_chat(id, user.id as _).await?;
+    Ok(Json(chat))
+}
+
+pub(crate) async fn leave_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state.leave_chat(id, user.id as _).await?;
+    Ok(Json(chat))
+}
+
 pub(crate) async fn create_chat_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
     Json(input): Json<CreateChat>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chat = state.create_chat(input, user.ws_id as _).await?;
+    let chat = state
+        .create_chat(input, user.ws_id as _, user.id as _)
+        .await?;
     Ok((StatusCode::CREATED, Json(chat)).into_response())
 }
 
@@ -37,12 +68,48 @@ pub(crate) async fn get_chat_handler(
     }
 }
 
-// TODO finish this as a homework
-pub(crate) async fn update_chat_handler() -> impl IntoResponse {
-    "update chat"
+pub(crate) async fn typing_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.send_typing(id, user.id as _).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+pub(crate) async fn mark_read_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<MarkRead>,
+) -> Result<impl IntoResponse, AppError> {
+    let read = state.mark_read(input, id, user.id as _).await?;
+    Ok(Json(read))
+}
+
+pub(crate) async fn update_chat_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<UpdateChat>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state.update_chat(id, input).await?;
+    Ok(Json(chat))
+}
+
+pub(crate) async fn delete_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.delete_chat(id, user.id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
 }
 
-// TODO finish this as a homework
-pub(crate) async fn delete_chat_handler() -> impl IntoResponse {
-    "delete chat"
+pub(crate) async fn update_member_role_handler(
+    State(state): State<AppState>,
+    Path((id, uid)): Path<(u64, u64)>,
+    Json(input): Json<UpdateMemberRole>,
+) -> Result<impl IntoResponse, AppError> {
+    let member = state.update_member_role(input, id, uid).await?;
+    Ok(Json(member))
 }
@@ -1,6 +1,6 @@
 use axum::{
     extract::{Multipart, Path, Query, State},
-    http::HeaderMap,
+    http::{HeaderMap, StatusCode},
     response::IntoResponse,
     Extension, Json,
 };
@@ -9,7 +9,10 @@ use chat_core::User;
 use tokio::fs;
 use tracing::{info, warn};
 
-use crate::{AppError, AppState, ChatFile, CreateMessage, ListMessage};
+use crate::{
+    AppError, AppState, ChatFile, CreateMessage, EditMessage, ListMessage, ReactionInput,
+    SearchMessage,
+};
 
 pub(crate) async fn send_message_handler(
     Extension(user): Extension<User>,
@@ -21,13 +24,72 @@ pub(crate) async fn send_message_handler(
     Ok(Json(msg))
 }
 
+pub(crate) async fn edit_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(u64, u64)>,
+    Json(input): Json<EditMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let msg = state.edit_message(input, id, mid, user.id as _).await?;
+    Ok(Json(msg))
+}
+
+pub(crate) async fn delete_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    state.delete_message(id, mid, user.id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn list_thread_handler(
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let messages = state.list_thread(id, mid).await?;
+    Ok(Json(messages))
+}
+
+pub(crate) async fn add_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(u64, u64)>,
+    Json(input): Json<ReactionInput>,
+) -> Result<impl IntoResponse, AppError> {
+    let reactions = state.add_reaction(input, id, mid, user.id as _).await?;
+    Ok(Json(reactions))
+}
+
+pub(crate) async fn remove_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid, emoji)): Path<(u64, u64, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    // DELETE bodies get dropped by some clients and proxies, the emoji is in the path
+    let input = ReactionInput { emoji };
+    let reactions = state.remove_reaction(input, id, mid, user.id as _).await?;
+    Ok(Json(reactions))
+}
+
 pub(crate) async fn list_message_handler(
     State(state): State<AppState>,
     Path(id): Path<u64>,
     Query(input): Query<ListMessage>,
 ) -> Result<impl IntoResponse, AppError> {
-    let messages = state.list_messages(input, id).await?;
-    Ok(Json(messages))
+    let page = state.list_messages(input, id).await?;
+    Ok(Json(page))
+}
+
+pub(crate) async fn search_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<SearchMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let hits = state
+        .search_messages(input, user.ws_id as _, user.id as _)
+        .await?;
+    Ok(Json(hits))
 }
 
 pub(crate) async fn download_file_handler(
@@ -35,7 +97,8 @@ pub(crate) async fn download_file_handler(
     State(state): State<AppState>,
     Path((ws_id, path)): Path<(i64, String)>,
 ) -> Result<impl IntoResponse, AppError> {
-    if user.ws_id != ws_id {
+    // files belong to a workspace, not to the one the token is scoped to
+    if !state.is_workspace_member(ws_id as _, user.id as _).await? {
         return Err(AppError::NotFound(
             "File not found or you don't have permission".to_string(),
         ));
@@ -74,7 +137,7 @@ pub(crate) async fn upload_handler(
         };
 
         let file = ChatFile::new(ws_id, &filename, &data);
-        let path = file.path(&base_dir);
+        let path = file.path(base_dir);
         if path.exists() {
             info!("File {} already exists: {:?}", filename, path);
         } else {
@@ -1,7 +1,12 @@
-use axum::{extract::State, response::IntoResponse, Extension, Json};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
 use chat_core::User;
 
-use crate::{AppError, AppState};
+use crate::{AppError, AppState, CreateInvite, JoinWorkspace, TransferWorkspace, UpdateWorkspace};
 
 pub(crate) async fn list_chat_users_handler(
     Extension(user): Extension<User>,
@@ -10,3 +15,103 @@ pub(crate) async fn list_chat_users_handler(
     let users = state.fetch_chat_users(user.ws_id as _).await?;
     Ok(Json(users))
 }
+
+pub(crate) async fn get_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    match state.find_workspace_by_id(user.ws_id as _).await? {
+        Some(ws) => Ok(Json(ws)),
+        None => Err(AppError::NotFound(format!("workspace id {}", user.ws_id))),
+    }
+}
+
+pub(crate) async fn list_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let workspaces = state.fetch_user_workspaces(user.id as _).await?;
+    Ok(Json(workspaces))
+}
+
+pub(crate) async fn join_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<JoinWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .join_workspace(input, user.id as _, &user.email)
+        .await?;
+    Ok((StatusCode::CREATED, Json(ws)))
+}
+
+pub(crate) async fn update_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .rename_workspace(input, user.ws_id as _, user.id as _)
+        .await?;
+    Ok(Json(ws))
+}
+
+pub(crate) async fn transfer_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<TransferWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .transfer_workspace(input, user.ws_id as _, user.id as _)
+        .await?;
+    Ok(Json(ws))
+}
+
+pub(crate) async fn remove_workspace_member_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .remove_workspace_member(user.ws_id as _, id, user.id as _)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn list_presence_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let presence = state.fetch_presence(user.ws_id as _).await?;
+    Ok(Json(presence))
+}
+
+pub(crate) async fn create_invite_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateInvite>,
+) -> Result<impl IntoResponse, AppError> {
+    let invite = state
+        .create_invite(input, user.ws_id as _, user.id as _)
+        .await?;
+    Ok((StatusCode::CREATED, Json(invite)))
+}
+
+pub(crate) async fn list_invite_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let invites = state.fetch_invites(user.ws_id as _, user.id as _).await?;
+    Ok(Json(invites))
+}
+
+pub(crate) async fn revoke_invite_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .revoke_invite(id, user.ws_id as _, user.id as _)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -3,18 +3,23 @@ mod error;
 mod handlers;
 mod middlewares;
 mod models;
+mod revocation;
 
 use anyhow::Context;
-use chat_core::{set_layer, verriy_token, DecodingKey, EncodingKey, TokenVeirfy, User};
+use chat_core::{
+    set_layer, verriy_token, DecodingKey, EncodingKey, RevocationList, TokenSession, TokenVeirfy,
+    User,
+};
 use handlers::*;
-use middlewares::verify_chat;
+use middlewares::{require_chat_permission, verify_chat, ChatPermission};
 use sqlx::PgPool;
 use std::{fmt, ops::Deref, sync::Arc};
 use tokio::fs;
 
 use axum::{
+    handler::Handler,
     middleware::from_fn_with_state,
-    routing::{get, post},
+    routing::{delete, get, patch, post},
     Router,
 };
 pub use config::AppConfig;
@@ -32,39 +37,102 @@ pub(crate) struct AppStateInner {
     pub(crate) dk: DecodingKey,
     pub(crate) ek: EncodingKey,
     pub(crate) pool: PgPool,
+    pub(crate) revocations: RevocationList,
 }
 
 impl TokenVeirfy for AppState {
     type Err = AppError;
 
-    fn verify(&self, token: &str) -> Result<User, Self::Err> {
-        Ok(self.dk.verify(token)?)
+    fn verify(&self, token: &str) -> Result<(User, TokenSession), Self::Err> {
+        let (user, session) = self.dk.verify(token)?;
+        if self.revocations.is_revoked(&session) {
+            return Err(AppError::TokenRevoked(format!(
+                "session {} of user {}",
+                session.sid, user.id
+            )));
+        }
+        Ok((user, session))
     }
 }
 
 pub async fn get_router(config: AppConfig) -> Result<Router, AppError> {
     let state = AppState::try_new(config).await?;
+    revocation::setup_revocation_listener(state.clone());
     let chat = Router::new()
         .route(
             "/:id",
             get(get_chat_handler)
-                .patch(update_chat_handler)
-                .delete(delete_chat_handler)
+                .patch(update_chat_handler.layer(from_fn_with_state(
+                    ChatPermission::UpdateChat,
+                    require_chat_permission,
+                )))
+                .delete(delete_chat_handler.layer(from_fn_with_state(
+                    ChatPermission::DeleteChat,
+                    require_chat_permission,
+                )))
                 .post(send_message_handler),
         )
+        .route(
+            "/:id/members/:uid",
+            patch(update_member_role_handler.layer(from_fn_with_state(
+                ChatPermission::ManageRoles,
+                require_chat_permission,
+            ))),
+        )
         .route("/:id/messages", get(list_message_handler))
+        .route(
+            "/:id/messages/:mid",
+            patch(edit_message_handler).delete(delete_message_handler),
+        )
+        .route("/:id/messages/:mid/thread", get(list_thread_handler))
+        .route("/:id/messages/:mid/reactions", post(add_reaction_handler))
+        .route(
+            "/:id/messages/:mid/reactions/:emoji",
+            delete(remove_reaction_handler),
+        )
+        .route("/:id/typing", post(typing_handler))
+        .route("/:id/read", post(mark_read_handler))
+        .route("/:id/leave", post(leave_chat_handler))
         .layer(from_fn_with_state(state.clone(), verify_chat))
+        // joining is for non-members, so it's not behind verify_chat
+        .route("/:id/join", post(join_chat_handler))
         .route("/", get(list_chat_handler).post(create_chat_handler));
 
     let api = Router::new()
         .route("/users", get(list_chat_users_handler))
+        .route("/users/presence", get(list_presence_handler))
+        .route(
+            "/workspace",
+            get(get_workspace_handler).patch(update_workspace_handler),
+        )
+        .route("/workspace/transfer", post(transfer_workspace_handler))
+        .route("/auth/sessions", delete(revoke_sessions_handler))
+        .route("/auth/token", delete(revoke_token_handler))
+        .route("/workspaces", get(list_workspace_handler))
+        .route("/workspaces/join", post(join_workspace_handler))
+        .route("/workspaces/:id/switch", post(switch_workspace_handler))
+        .route("/workspace/members", get(list_chat_users_handler))
+        .route(
+            "/workspace/members/:id",
+            delete(remove_workspace_member_handler),
+        )
+        .route(
+            "/invites",
+            get(list_invite_handler).post(create_invite_handler),
+        )
+        .route("/invites/:id", delete(revoke_invite_handler))
         .nest("/chats", chat)
+        .route("/search", get(search_message_handler))
+        .route("/channels", get(list_channel_handler))
         .route("/upload", post(upload_handler))
         .route("/files/:ws_id/*path", get(download_file_handler))
         .layer(from_fn_with_state(state.clone(), verriy_token::<AppState>))
         // routes doesn't need token verification
         .route("/signin", post(signin_handler))
-        .route("/signup", post(signup_handler));
+        .route("/signup", post(signup_handler))
+        // access tokens may be expired, the refresh token authenticates
+        .route("/auth/refresh", post(refresh_handler))
+        .route("/auth/logout", post(logout_handler));
 
     let app = Router::new()
         .route("/", get(index_handler))
@@ -99,6 +167,7 @@ impl AppState {
                 ek,
                 dk,
                 pool,
+                revocations: RevocationList::default(),
             }),
         })
     }
@@ -135,6 +204,7 @@ mod test_util {
                         ek,
                         dk,
                         pool,
+                        revocations: RevocationList::default(),
                     }),
                 },
             ))
@@ -3,32 +3,78 @@ use axum::{
     middleware::Next,
     response::{IntoResponse, Response},
 };
-use chat_core::User;
+use chat_core::{ChatRole, User};
+use std::collections::HashMap;
 
 use crate::{AppError, AppState};
 
+// what a route needs to be allowed on a chat, checked against the `ChatRole`
+// verify_chat puts in the request extensions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatPermission {
+    // rename, add or remove members
+    UpdateChat,
+    DeleteChat,
+    ManageRoles,
+}
+
+impl ChatPermission {
+    pub fn allows(&self, role: ChatRole) -> bool {
+        match self {
+            Self::UpdateChat | Self::DeleteChat => role.is_admin(),
+            Self::ManageRoles => role == ChatRole::Owner,
+        }
+    }
+}
+
 // write a axum middleware to verify chat
 pub async fn verify_chat(state: State<AppState>, req: Request, next: Next) -> Response {
     let (mut parts, body) = req.into_parts();
-    let Path(chat_id) = Path::<u64>::from_request_parts(&mut parts, &state)
-        .await
-        .unwrap();
+    // chat routes may carry more params, e.g. /:id/messages/:mid
+    let params = match Path::<HashMap<String, u64>>::from_request_parts(&mut parts, &state).await {
+        Ok(Path(params)) => params,
+        Err(e) => return e.into_response(),
+    };
+    let Some(&chat_id) = params.get("id") else {
+        return AppError::NotFound("chat id".to_string()).into_response();
+    };
 
     let user = parts.extensions.get::<User>().unwrap();
-    if !state
-        .is_chat_member(chat_id, user.id as _)
-        .await
-        .unwrap_or_default()
-    {
-        let err =
-            AppError::CreateMessageError(format!("User {} are not a member of {chat_id}", user.id));
-        return err.into_response();
-    }
+    let role = match state.fetch_chat_role(chat_id, user.id as _).await {
+        Ok(Some(role)) => role,
+        Ok(None) => {
+            let err = AppError::PermissionDenied(format!(
+                "User {} is not a member of {chat_id}",
+                user.id
+            ));
+            return err.into_response();
+        }
+        Err(e) => return e.into_response(),
+    };
+    parts.extensions.insert(role);
 
     let req = Request::from_parts(parts, body);
     next.run(req).await
 }
 
+// routes declare their permission with
+// `handler.layer(from_fn_with_state(ChatPermission::X, require_chat_permission))`,
+// it must run after verify_chat
+pub async fn require_chat_permission(
+    State(permission): State<ChatPermission>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(&role) = req.extensions().get::<ChatRole>() else {
+        return AppError::PermissionDenied("chat role is unknown".to_string()).into_response();
+    };
+    if !permission.allows(role) {
+        let err = AppError::PermissionDenied(format!("{role:?} can't {permission:?}"));
+        return err.into_response();
+    }
+    next.run(req).await
+}
+
 // verify file
 
 #[cfg(test)]
@@ -36,7 +82,9 @@ mod tests {
     use super::*;
     use anyhow::Result;
     use axum::http::StatusCode;
-    use axum::{body::Body, middleware::from_fn_with_state, routing::get, Router};
+    use axum::{
+        body::Body, handler::Handler, middleware::from_fn_with_state, routing::get, Router,
+    };
     use chat_core::verriy_token;
     use tower::ServiceExt;
 
@@ -48,7 +96,7 @@ mod tests {
     async fn verify_chat_middleware_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
         let user = state.find_user_by_id(1).await?.expect("user should exist");
-        let token = state.ek.sign(user)?;
+        let token = state.ek.sign(user, "session")?;
 
         let app = Router::new()
             .route("/chat/:id/messages", get(handler))
@@ -70,7 +118,55 @@ mod tests {
             .header("authorization", format!("Bearer {}", token))
             .body(Body::empty())?;
         let res = app.clone().oneshot(req).await?;
-        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn require_chat_permission_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let app = Router::new()
+            .route(
+                "/chat/:id",
+                get(handler).delete(handler.layer(from_fn_with_state(
+                    ChatPermission::DeleteChat,
+                    require_chat_permission,
+                ))),
+            )
+            .layer(from_fn_with_state(state.clone(), verify_chat))
+            .layer(from_fn_with_state(state.clone(), verriy_token::<AppState>))
+            .with_state(state.clone());
+
+        let send = |user_id: i64, method: &'static str| {
+            let app = app.clone();
+            let state = state.clone();
+            async move {
+                let user = state.find_user_by_id(user_id as _).await?.unwrap();
+                let req = Request::builder()
+                    .method(method)
+                    .uri("/chat/1")
+                    .header(
+                        "authorization",
+                        format!("Bearer {}", state.ek.sign(user, "session")?),
+                    )
+                    .body(Body::empty())?;
+                Ok::<_, anyhow::Error>(app.oneshot(req).await?.status())
+            }
+        };
+
+        // every member can read, only admins can delete
+        assert_eq!(send(2, "GET").await?, StatusCode::OK);
+        assert_eq!(send(2, "DELETE").await?, StatusCode::FORBIDDEN);
+        assert_eq!(send(1, "DELETE").await?, StatusCode::OK);
+        Ok(())
+    }
+
+    #[test]
+    fn chat_permission_should_match_role() {
+        assert!(ChatPermission::UpdateChat.allows(ChatRole::Admin));
+        assert!(!ChatPermission::UpdateChat.allows(ChatRole::Member));
+        assert!(ChatPermission::DeleteChat.allows(ChatRole::Owner));
+        assert!(!ChatPermission::ManageRoles.allows(ChatRole::Admin));
+        assert!(ChatPermission::ManageRoles.allows(ChatRole::Owner));
+    }
 }
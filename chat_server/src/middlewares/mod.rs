@@ -1,2 +1,2 @@
 mod chat;
-pub use chat::verify_chat;
+pub use chat::{require_chat_permission, verify_chat, ChatPermission};
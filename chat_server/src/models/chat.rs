@@ -1,7 +1,11 @@
-use chat_core::{Chat, ChatType};
-use std::str::FromStr;
+use chat_core::{
+    Chat, ChatMember, ChatRole, ChatType, ChatTyping, CHAT_TYPING, NOTIFICATION_VERSION,
+};
+use chrono::{DateTime, Utc};
+use std::{collections::HashSet, str::FromStr};
 
 use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgExecutor, Postgres, Transaction};
 
 use crate::{AppError, AppState};
 
@@ -14,78 +18,402 @@ pub struct CreateChat {
     pub public: bool,
 }
 
+// partial update, an empty name clears the chat name
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateChat {
+    pub name: Option<String>,
+    #[serde(default)]
+    pub add_members: Vec<i64>,
+    #[serde(default)]
+    pub remove_members: Vec<i64>,
+    pub public: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateMemberRole {
+    pub role: ChatRole,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListChat {
+    pub r#type: Option<ChatType>,
+    // `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<u64>,
+}
+
+// a chat as listed for a user
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ChatSummary {
+    #[serde(flatten)]
+    pub chat: Chat,
+    // messages from others after the user's last read message
+    pub unread: i64,
+    // the latest message time, or the creation time of an empty chat
+    pub last_activity_at: DateTime<Utc>,
+    pub last_message: Option<MessagePreview>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MessagePreview {
+    pub id: i64,
+    pub sender_id: i64,
+    // the first PREVIEW_LEN characters of the content
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// chats ordered by last activity, newest first
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ChatPage {
+    pub chats: Vec<ChatSummary>,
+    pub has_more: bool,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, FromRow)]
+struct ChatRow {
+    #[sqlx(flatten)]
+    chat: Chat,
+    unread: i64,
+    last_activity_at: DateTime<Utc>,
+    last_message_id: Option<i64>,
+    last_sender_id: Option<i64>,
+    last_content: Option<String>,
+    last_message_at: Option<DateTime<Utc>>,
+}
+
+const DEFAULT_CHAT_LIMIT: u64 = 50;
+const MAX_CHAT_LIMIT: u64 = 200;
+const PREVIEW_LEN: i32 = 100;
+
 #[allow(dead_code)]
 impl AppState {
-    pub async fn create_chat(&self, input: CreateChat, ws_id: u64) -> Result<Chat, AppError> {
+    pub async fn create_chat(
+        &self,
+        input: CreateChat,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<Chat, AppError> {
         let len = input.members.len();
-        if len < 2 {
+        if let Err(msg) = validate_chat(input.name.as_deref(), len) {
+            return Err(AppError::CreateChatError(msg));
+        }
+
+        // the creator owns the chat, so they have to be in it
+        if !input.members.contains(&(user_id as i64)) {
             return Err(AppError::CreateChatError(
-                "Chat must have at least 2 members".to_string(),
+                "Chat creator must be a member".to_string(),
             ));
         }
 
-        if len > 8 && input.name.is_none() {
+        // verify if all members exist and belong to the workspace
+        let users = self
+            .fetch_workspace_users_by_ids(ws_id, &input.members)
+            .await?;
+        let found: HashSet<_> = users.iter().map(|u| u.id).collect();
+        if input.members.iter().any(|id| !found.contains(id)) {
             return Err(AppError::CreateChatError(
-                "Group chat with more 8 members must have a name".to_string(),
+                "Some members do not exist in the workspace".to_string(),
             ));
         }
 
-        // verify if all members exist
-        let users = self.fetch_chat_user_by_ids(&input.members).await?;
-        if users.len() != len {
-            return Err(AppError::CreateChatError(
-                "Some members do not exist".to_string(),
+        let chat_type = get_chat_type(input.name.as_deref(), len, input.public);
+
+        let mut tx = self.pool.begin().await?;
+        let (id,): (i64,) = sqlx::query_as(
+            r#"
+            INSERT INTO chats (ws_id, owner_id, name, type)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .bind(&input.name)
+        .bind(chat_type)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // the creator owns the chat
+        sqlx::query(
+            r#"
+            INSERT INTO chat_members (chat_id, user_id, role)
+            SELECT $1, m, CASE WHEN m = $3 THEN 'owner'::chat_role ELSE 'member'::chat_role END
+            FROM unnest($2::bigint[]) AS m
+            "#,
+        )
+        .bind(id)
+        .bind(&input.members)
+        .bind(user_id as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        let chat = fetch_chat(&mut tx, id as _).await?;
+        tx.commit().await?;
+        Ok(chat)
+    }
+
+    pub async fn update_chat(&self, id: u64, input: UpdateChat) -> Result<Chat, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let chat = lock_chat(&mut tx, id).await?;
+
+        if input.remove_members.contains(&chat.owner_id) {
+            return Err(AppError::UpdateChatError(
+                "Chat owner can't be removed".to_string(),
             ));
         }
 
-        let chat_type = match (&input.name, len) {
-            (None, 2) => ChatType::Single,
-            (None, 3..=8) => ChatType::Group,
-            (Some(_), _) => {
-                if input.public {
-                    ChatType::PublicChannel
-                } else {
-                    ChatType::PrivateChannel
-                }
+        // verify if all new members exist and belong to the chat's workspace
+        if !input.add_members.is_empty() {
+            let users = self
+                .fetch_workspace_users_by_ids(chat.ws_id as _, &input.add_members)
+                .await?;
+            let found: HashSet<_> = users.iter().map(|u| u.id).collect();
+            if input.add_members.iter().any(|id| !found.contains(id)) {
+                return Err(AppError::UpdateChatError(
+                    "Some members do not exist in the workspace".to_string(),
+                ));
+            }
+        }
+
+        let mut members = chat.members;
+        members.retain(|id| !input.remove_members.contains(id));
+        for id in &input.add_members {
+            if !members.contains(id) {
+                members.push(*id);
             }
-            _ => ChatType::Single,
+        }
+
+        let name = match input.name {
+            Some(name) if name.is_empty() => None,
+            Some(name) => Some(name),
+            None => chat.name,
         };
+        let public = input
+            .public
+            .unwrap_or(chat.r#type == ChatType::PublicChannel);
+
+        let len = members.len();
+        if let Err(msg) = validate_chat(name.as_deref(), len) {
+            return Err(AppError::UpdateChatError(msg));
+        }
+        let chat_type = get_chat_type(name.as_deref(), len, public);
+
+        if !input.remove_members.is_empty() {
+            sqlx::query("DELETE FROM chat_members WHERE chat_id = $1 AND user_id = ANY($2)")
+                .bind(id as i64)
+                .bind(&input.remove_members)
+                .execute(&mut *tx)
+                .await?;
+        }
+        if !input.add_members.is_empty() {
+            sqlx::query(
+                r#"
+                INSERT INTO chat_members (chat_id, user_id)
+                SELECT $1, unnest($2::bigint[])
+                ON CONFLICT DO NOTHING
+                "#,
+            )
+            .bind(id as i64)
+            .bind(&input.add_members)
+            .execute(&mut *tx)
+            .await?;
+        }
 
         let chat = sqlx::query_as(
             r#"
-            INSERT INTO chats (ws_id, name, type, members)
-            VALUES ($1, $2, $3, $4)
-            RETURNING id, ws_id, name, type, members, created_at
+            UPDATE chats
+            SET name = $1, type = $2
+            WHERE id = $3
+            RETURNING id, ws_id, owner_id, name, type, chat_member_ids(id) AS members, created_at
             "#,
         )
-        .bind(ws_id as i64)
-        .bind(&input.name)
+        .bind(&name)
         .bind(chat_type)
-        .bind(input.members)
-        .fetch_one(&self.pool)
+        .bind(id as i64)
+        .fetch_one(&mut *tx)
         .await?;
+        tx.commit().await?;
         Ok(chat)
     }
 
-    pub async fn fetch_chats(&self, ws_id: u64) -> Result<Vec<Chat>, AppError> {
+    // only chat admins can delete a chat, the workspace owner is the owner of
+    // every chat they are in
+    pub async fn delete_chat(&self, id: u64, user_id: u64) -> Result<Chat, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let chat = lock_chat(&mut tx, id).await?;
+
+        let role = fetch_chat_role(&mut *tx, id, user_id).await?;
+        if !role.is_some_and(|role| role.is_admin()) {
+            return Err(AppError::PermissionDenied(format!(
+                "User {user_id} can't delete chat {id}"
+            )));
+        }
+
+        sqlx::query("DELETE FROM messages WHERE chat_id = $1")
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM chats WHERE id = $1")
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(chat)
+    }
+
+    // chats the user is a member of, plus the public channels of the workspace
+    pub async fn fetch_chats(
+        &self,
+        input: ListChat,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<ChatPage, AppError> {
+        let limit = input
+            .limit
+            .unwrap_or(DEFAULT_CHAT_LIMIT)
+            .clamp(1, MAX_CHAT_LIMIT) as i64;
+        let cursor = input.cursor.as_deref().map(parse_chat_cursor).transpose()?;
+        let (cursor_at, cursor_id) = cursor.unzip();
+
+        let mut rows: Vec<ChatRow> = sqlx::query_as(
+            r#"
+            SELECT c.id, c.ws_id, c.owner_id, c.name, c.type, chat_member_ids(c.id) AS members,
+                c.created_at,
+                -- public channels the user hasn't joined have nothing unread
+                CASE WHEN me.user_id IS NULL THEN 0 ELSE (
+                    SELECT COUNT(*)
+                    FROM messages m
+                    WHERE m.chat_id = c.id
+                    AND m.id > COALESCE(r.last_read_message_id, 0)
+                    AND m.deleted_at IS NULL
+                    AND m.sender_id <> $2
+                ) END AS unread,
+                COALESCE(lm.created_at, c.created_at) AS last_activity_at,
+                lm.id AS last_message_id,
+                lm.sender_id AS last_sender_id,
+                left(lm.content, $6) AS last_content,
+                lm.created_at AS last_message_at
+            FROM chats c
+            LEFT JOIN chat_members me ON me.chat_id = c.id AND me.user_id = $2
+            LEFT JOIN chat_reads r ON r.chat_id = c.id AND r.user_id = $2
+            LEFT JOIN LATERAL (
+                SELECT id, sender_id, content, created_at
+                FROM messages
+                WHERE chat_id = c.id AND deleted_at IS NULL
+                ORDER BY id DESC
+                LIMIT 1
+            ) lm ON true
+            WHERE c.ws_id = $1
+            AND (c.type = 'public_channel' OR me.user_id IS NOT NULL)
+            AND ($3::chat_type IS NULL OR c.type = $3)
+            AND ($4::timestamptz IS NULL OR (COALESCE(lm.created_at, c.created_at), c.id) < ($4, $5))
+            ORDER BY last_activity_at DESC, c.id DESC
+            LIMIT $7
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .bind(input.r#type)
+        .bind(cursor_at)
+        .bind(cursor_id)
+        .bind(PREVIEW_LEN)
+        .bind(limit + 1)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let has_more = rows.len() > limit as usize;
+        rows.truncate(limit as usize);
+        let next_cursor = rows.last().filter(|_| has_more).map(|row| {
+            format!(
+                "{}_{}",
+                row.last_activity_at.timestamp_micros(),
+                row.chat.id
+            )
+        });
+        let chats = rows.into_iter().map(ChatSummary::from).collect();
+
+        Ok(ChatPage {
+            chats,
+            has_more,
+            next_cursor,
+        })
+    }
+
+    pub async fn fetch_channels(&self, ws_id: u64) -> Result<Vec<Chat>, AppError> {
         let chats = sqlx::query_as(
             r#"
-            SELECT id, ws_id, name, type, members, created_at
+            SELECT id, ws_id, owner_id, name, type, chat_member_ids(id) AS members, created_at
             FROM chats
-            WHERE ws_id = $1
+            WHERE ws_id = $1 AND type = 'public_channel'
+            ORDER BY name, id
             "#,
         )
         .bind(ws_id as i64)
         .fetch_all(&self.pool)
         .await?;
-
         Ok(chats)
     }
 
+    // joining twice is a no-op, channels are only open to their workspace
+    pub async fn join_chat(&self, id: u64, user_id: u64) -> Result<Chat, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let chat = lock_chat(&mut tx, id).await?;
+        if chat.members.contains(&(user_id as i64)) {
+            return Ok(chat);
+        }
+        if !self.is_workspace_member(chat.ws_id as _, user_id).await? {
+            return Err(AppError::NotFound(format!("chat id {id}")));
+        }
+        if chat.r#type != ChatType::PublicChannel {
+            return Err(AppError::PermissionDenied(format!(
+                "Chat {id} is not a public channel"
+            )));
+        }
+
+        sqlx::query("INSERT INTO chat_members (chat_id, user_id) VALUES ($1, $2)")
+            .bind(id as i64)
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        let chat = fetch_chat(&mut tx, id).await?;
+        tx.commit().await?;
+        Ok(chat)
+    }
+
+    // only channels can be left, and they keep at least 2 members
+    pub async fn leave_chat(&self, id: u64, user_id: u64) -> Result<Chat, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let chat = lock_chat(&mut tx, id).await?;
+        if !chat.members.contains(&(user_id as i64)) {
+            return Err(AppError::NotFound(format!("chat id {id}")));
+        }
+        let is_channel = matches!(
+            chat.r#type,
+            ChatType::PublicChannel | ChatType::PrivateChannel
+        );
+        // the owner would leave the chat without anyone to manage it
+        if !is_channel || chat.members.len() <= 2 || chat.owner_id == user_id as i64 {
+            return Err(AppError::UpdateChatError(format!(
+                "User {user_id} can't leave chat {id}"
+            )));
+        }
+
+        sqlx::query("DELETE FROM chat_members WHERE chat_id = $1 AND user_id = $2")
+            .bind(id as i64)
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        let chat = fetch_chat(&mut tx, id).await?;
+        tx.commit().await?;
+        Ok(chat)
+    }
+
     pub async fn get_chat_by_id(&self, id: u64) -> Result<Option<Chat>, AppError> {
         let chat = sqlx::query_as(
             r#"
-            SELECT id, ws_id, name, type, members, created_at
+            SELECT id, ws_id, owner_id, name, type, chat_member_ids(id) AS members, created_at
             FROM chats
             WHERE id = $1
             "#,
@@ -96,12 +424,93 @@ impl AppState {
         Ok(chat)
     }
 
+    // typing events are relayed by notify_server, they're never stored
+    pub async fn send_typing(&self, chat_id: u64, user_id: u64) -> Result<(), AppError> {
+        let Some(chat) = self.get_chat_by_id(chat_id).await? else {
+            return Err(AppError::NotFound(format!("chat id {chat_id}")));
+        };
+        let payload = ChatTyping {
+            version: NOTIFICATION_VERSION,
+            chat_id: chat.id,
+            user_id: user_id as i64,
+            members: chat.members,
+        };
+        let payload = serde_json::to_string(&payload).expect("Failed to serialize typing");
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(CHAT_TYPING)
+            .bind(payload)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn fetch_chat_members(&self, chat_id: u64) -> Result<Vec<ChatMember>, AppError> {
+        let members = sqlx::query_as(
+            r#"
+            SELECT chat_id, user_id, role, joined_at, muted
+            FROM chat_members
+            WHERE chat_id = $1
+            ORDER BY joined_at, user_id
+            "#,
+        )
+        .bind(chat_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(members)
+    }
+
+    // the role of a member, the workspace owner acts as the owner of every
+    // chat they are in
+    pub async fn fetch_chat_role(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<Option<ChatRole>, AppError> {
+        fetch_chat_role(&self.pool, chat_id, user_id).await
+    }
+
+    // promote a member to admin or demote an admin, ownership can't be changed here
+    pub async fn update_member_role(
+        &self,
+        input: UpdateMemberRole,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<ChatMember, AppError> {
+        if input.role == ChatRole::Owner {
+            return Err(AppError::UpdateChatError(
+                "Chat owner can't be assigned".to_string(),
+            ));
+        }
+        let member: Option<ChatMember> = sqlx::query_as(
+            r#"
+            UPDATE chat_members
+            SET role = $1
+            WHERE chat_id = $2 AND user_id = $3 AND role <> 'owner'
+            RETURNING chat_id, user_id, role, joined_at, muted
+            "#,
+        )
+        .bind(input.role)
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        match member {
+            Some(member) => Ok(member),
+            None if self.is_chat_member(chat_id, user_id).await? => Err(AppError::UpdateChatError(
+                format!("Role of chat {chat_id} owner can't be changed"),
+            )),
+            None => Err(AppError::NotFound(format!(
+                "member {user_id} of chat {chat_id}"
+            ))),
+        }
+    }
+
     pub async fn is_chat_member(&self, chat_id: u64, user_id: u64) -> Result<bool, AppError> {
         let is_member = sqlx::query(
             r#"
             SELECT 1
-            FROM chats
-            WHERE id = $1 AND $2 = ANY(members)
+            FROM chat_members
+            WHERE chat_id = $1 AND user_id = $2
             "#,
         )
         .bind(chat_id as i64)
@@ -113,6 +522,119 @@ impl AppState {
     }
 }
 
+async fn fetch_chat_role(
+    executor: impl PgExecutor<'_>,
+    chat_id: u64,
+    user_id: u64,
+) -> Result<Option<ChatRole>, AppError> {
+    let role: Option<(ChatRole,)> = sqlx::query_as(
+        r#"
+        SELECT CASE WHEN w.owner_id = cm.user_id THEN 'owner'::chat_role ELSE cm.role END
+        FROM chat_members cm
+        JOIN chats c ON c.id = cm.chat_id
+        JOIN workspaces w ON w.id = c.ws_id
+        WHERE cm.chat_id = $1 AND cm.user_id = $2
+        "#,
+    )
+    .bind(chat_id as i64)
+    .bind(user_id as i64)
+    .fetch_optional(executor)
+    .await?;
+    Ok(role.map(|(role,)| role))
+}
+
+// membership changes lock the chat row first, so they are serialized
+// the chat's own workspace is authoritative, not the one the token is scoped to
+async fn lock_chat(tx: &mut Transaction<'_, Postgres>, id: u64) -> Result<Chat, AppError> {
+    let chat: Option<Chat> = sqlx::query_as(
+        r#"
+        SELECT id, ws_id, owner_id, name, type, chat_member_ids(id) AS members, created_at
+        FROM chats
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+    )
+    .bind(id as i64)
+    .fetch_optional(&mut **tx)
+    .await?;
+    chat.ok_or_else(|| AppError::NotFound(format!("chat id {id}")))
+}
+
+async fn fetch_chat(tx: &mut Transaction<'_, Postgres>, id: u64) -> Result<Chat, AppError> {
+    let chat = sqlx::query_as(
+        r#"
+        SELECT id, ws_id, owner_id, name, type, chat_member_ids(id) AS members, created_at
+        FROM chats
+        WHERE id = $1
+        "#,
+    )
+    .bind(id as i64)
+    .fetch_one(&mut **tx)
+    .await?;
+    Ok(chat)
+}
+
+fn validate_chat(name: Option<&str>, len: usize) -> Result<(), String> {
+    if len < 2 {
+        return Err("Chat must have at least 2 members".to_string());
+    }
+
+    if len > 8 && name.is_none() {
+        return Err("Group chat with more 8 members must have a name".to_string());
+    }
+    Ok(())
+}
+
+fn get_chat_type(name: Option<&str>, len: usize, public: bool) -> ChatType {
+    match (name, len) {
+        (None, 2) => ChatType::Single,
+        (None, 3..=8) => ChatType::Group,
+        (Some(_), _) => {
+            if public {
+                ChatType::PublicChannel
+            } else {
+                ChatType::PrivateChannel
+            }
+        }
+        _ => ChatType::Single,
+    }
+}
+
+impl From<ChatRow> for ChatSummary {
+    fn from(row: ChatRow) -> Self {
+        let last_message = match (
+            row.last_message_id,
+            row.last_sender_id,
+            row.last_content,
+            row.last_message_at,
+        ) {
+            (Some(id), Some(sender_id), Some(content), Some(created_at)) => Some(MessagePreview {
+                id,
+                sender_id,
+                content,
+                created_at,
+            }),
+            _ => None,
+        };
+        Self {
+            chat: row.chat,
+            unread: row.unread,
+            last_activity_at: row.last_activity_at,
+            last_message,
+        }
+    }
+}
+
+// the cursor is `<last activity in micros>_<chat id>`
+fn parse_chat_cursor(s: &str) -> Result<(DateTime<Utc>, i64), AppError> {
+    let err = || AppError::ListChatError(format!("Invalid cursor: {s}"));
+    let (micros, id) = s.split_once('_').ok_or_else(err)?;
+    let micros = micros.parse().map_err(|_| err())?;
+    let id = id.parse().map_err(|_| err())?;
+    let at = DateTime::from_timestamp_micros(micros).ok_or_else(err)?;
+    Ok((at, id))
+}
+
 impl FromStr for ChatFile {
     type Err = AppError;
 
@@ -146,7 +668,7 @@ impl FromStr for ChatFile {
 
         let hash = format!("{}{}{}", parts[1], parts[2], part3);
         Ok(Self {
-            ws_id: ws_id,
+            ws_id,
             ext: ext.to_string(),
             hash,
         })
@@ -163,9 +685,21 @@ impl CreateChat {
         };
 
         Self {
-            name: name,
+            name,
             members: members.to_vec(),
-            public: public,
+            public,
+        }
+    }
+}
+
+#[cfg(test)]
+impl UpdateChat {
+    pub fn new(name: Option<&str>, add: &[i64], remove: &[i64], public: Option<bool>) -> Self {
+        Self {
+            name: name.map(|v| v.to_string()),
+            add_members: add.to_vec(),
+            remove_members: remove.to_vec(),
+            public,
         }
     }
 }
@@ -173,7 +707,10 @@ impl CreateChat {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::CreateMessage;
     use anyhow::{Ok, Result};
+    use chat_core::parse_notification;
+    use sqlx::postgres::PgListener;
 
     #[tokio::test]
     async fn create_single_chat_should_work() -> Result<()> {
@@ -181,10 +718,11 @@ mod tests {
         let input = CreateChat::new("", &[1, 2], false);
 
         let chat = state
-            .create_chat(input, 1)
+            .create_chat(input, 1, 1)
             .await
             .expect("create chat failed");
         assert_eq!(chat.ws_id, 1);
+        assert_eq!(chat.owner_id, 1);
         assert_eq!(chat.members.len(), 2);
         assert_eq!(chat.r#type, ChatType::Single);
         Ok(())
@@ -196,7 +734,7 @@ mod tests {
         let input = CreateChat::new("general", &[1, 2, 3], true);
 
         let chat = state
-            .create_chat(input, 1)
+            .create_chat(input, 1, 1)
             .await
             .expect("create chat failed");
         assert_eq!(chat.ws_id, 1);
@@ -205,6 +743,108 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn create_chat_with_invalid_members_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // the creator isn't a member
+        let input = CreateChat::new("", &[2, 3], false);
+        let err = state.create_chat(input, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::CreateChatError(_)));
+
+        // tom exists, but in another workspace
+        let input = crate::CreateUser::new("other", "tom", "tom@other.org", "123456");
+        let tom = state.create_user(&input).await?;
+        let input = CreateChat::new("", &[1, tom.id], false);
+        let err = state.create_chat(input, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::CreateChatError(_)));
+
+        // a user removed from the workspace can't be added back
+        state.update_workspace_owner(1, 1).await?;
+        state.remove_workspace_member(1, 2, 1).await?;
+        let input = CreateChat::new("", &[1, 2], false);
+        let err = state.create_chat(input, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::CreateChatError(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_chat_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // add a member to the single chat makes it a group
+        let input = UpdateChat::new(None, &[3], &[], None);
+        let chat = state.update_chat(3, input).await?;
+        assert_eq!(chat.members, vec![1, 2, 3]);
+        assert_eq!(chat.r#type, ChatType::Group);
+
+        // naming a group makes it a channel
+        let input = UpdateChat::new(Some("dev"), &[], &[2], Some(true));
+        let chat = state.update_chat(3, input).await?;
+        assert_eq!(chat.name.as_deref(), Some("dev"));
+        assert_eq!(chat.members, vec![1, 3]);
+        assert_eq!(chat.r#type, ChatType::PublicChannel);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_chat_with_invalid_input_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // the owner can't be removed
+        let input = UpdateChat::new(None, &[], &[1], None);
+        let err = state.update_chat(3, input).await.unwrap_err();
+        assert!(matches!(err, AppError::UpdateChatError(_)));
+
+        // chat needs at least 2 members
+        let input = UpdateChat::new(None, &[], &[2], None);
+        let err = state.update_chat(3, input).await.unwrap_err();
+        assert!(matches!(err, AppError::UpdateChatError(_)));
+
+        // user 10 doesn't exist
+        let input = UpdateChat::new(None, &[10], &[], None);
+        let err = state.update_chat(3, input).await.unwrap_err();
+        assert!(matches!(err, AppError::UpdateChatError(_)));
+
+        let input = UpdateChat::new(Some("dev"), &[], &[], None);
+        let err = state.update_chat(100, input).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_chat_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // user 2 is neither the chat owner nor the owner of the chat's
+        // workspace, owning another one doesn't count
+        state.create_workspace("other", 2).await?;
+        let err = state.delete_chat(1, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        // chat owner can delete the chat with its messages
+        let chat = state.delete_chat(1, 1).await?;
+        assert_eq!(chat.id, 1);
+        assert!(state.get_chat_by_id(1).await?.is_none());
+
+        // the workspace owner can delete the chats they are in
+        state.update_workspace_owner(1, 3).await?;
+        state.delete_chat(4, 3).await?;
+        assert!(state.get_chat_by_id(4).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_typing_should_notify() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut listener = PgListener::connect_with(&state.pool).await?;
+        listener.listen(CHAT_TYPING).await?;
+
+        state.send_typing(3, 1).await?;
+        let notif = listener.recv().await?;
+        let payload: ChatTyping = parse_notification(notif.payload())?;
+        assert_eq!(payload.chat_id, 3);
+        assert_eq!(payload.user_id, 1);
+        assert_eq!(payload.members, vec![1, 2]);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn chat_get_by_id_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
@@ -223,9 +863,206 @@ mod tests {
     #[tokio::test]
     async fn chat_fetch_all_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
-        let chats = state.fetch_chats(1).await.expect("fetch all chats failed");
+        let chats = state
+            .fetch_chats(ListChat::default(), 1, 1)
+            .await
+            .expect("fetch all chats failed")
+            .chats;
 
         assert_eq!(chats.len(), 4);
+        // user 1 hasn't read anything in chat 1
+        let chat = chats.iter().find(|c| c.chat.id == 1).unwrap();
+        assert_eq!(chat.unread, 6);
+        assert_eq!(chat.last_message.as_ref().unwrap().id, 10);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_fetch_should_filter_and_paginate() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // user 4 is only in chat 1, but sees other public channels too
+        let input = CreateChat {
+            name: Some("dev".to_string()),
+            members: vec![1, 2, 3],
+            public: true,
+        };
+        let dev = state.create_chat(input, 1, 1).await?;
+        let input = CreateMessage {
+            content: "hi".to_string(),
+            files: vec![],
+            reply_to: None,
+        };
+        state.create_message(input, dev.id as _, 1).await?;
+        let chats = state.fetch_chats(ListChat::default(), 1, 4).await?.chats;
+        let ids: HashSet<_> = chats.iter().map(|c| c.chat.id).collect();
+        assert_eq!(ids, HashSet::from([1, dev.id]));
+        // nothing is unread in a channel user 4 hasn't joined
+        let chat = chats.iter().find(|c| c.chat.id == dev.id).unwrap();
+        assert_eq!(chat.unread, 0);
+
+        let input = ListChat {
+            r#type: Some(ChatType::Single),
+            ..Default::default()
+        };
+        let chats = state.fetch_chats(input, 1, 1).await?.chats;
+        assert_eq!(chats.len(), 1);
+        assert_eq!(chats[0].chat.id, 3);
+
+        // the chat with the latest message comes first
+        let input = CreateMessage {
+            content: "ping".to_string(),
+            files: vec![],
+            reply_to: None,
+        };
+        state.create_message(input, 4, 1).await?;
+        let input = ListChat {
+            limit: Some(3),
+            ..Default::default()
+        };
+        let page = state.fetch_chats(input, 1, 1).await?;
+        assert_eq!(page.chats[0].chat.id, 4);
+        assert_eq!(page.chats[0].last_message.as_ref().unwrap().content, "ping");
+        assert!(page.has_more);
+
+        let input = ListChat {
+            cursor: page.next_cursor,
+            limit: Some(3),
+            ..Default::default()
+        };
+        let next = state.fetch_chats(input, 1, 1).await?;
+        assert_eq!(next.chats.len(), 2);
+        assert!(!next.has_more);
+        let mut ids: Vec<_> = page
+            .chats
+            .iter()
+            .chain(&next.chats)
+            .map(|c| c.chat.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2, 3, 4, dev.id]);
+
+        let input = ListChat {
+            cursor: Some("abc".to_string()),
+            ..Default::default()
+        };
+        let err = state.fetch_chats(input, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::ListChatError(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn join_and_leave_channel_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateChat {
+            name: Some("dev".to_string()),
+            members: vec![1, 2],
+            public: true,
+        };
+        let dev = state.create_chat(input, 1, 1).await?;
+        let channels = state.fetch_channels(1).await?;
+        assert_eq!(channels.len(), 2);
+
+        // concurrent joins are all kept
+        let (a, b) = tokio::join!(
+            state.join_chat(dev.id as _, 3),
+            state.join_chat(dev.id as _, 4)
+        );
+        a?;
+        b?;
+        let chat = state.join_chat(dev.id as _, 4).await?;
+        assert_eq!(chat.members.len(), 4);
+
+        let chat = state.leave_chat(dev.id as _, 3).await?;
+        assert!(!chat.members.contains(&3));
+
+        // private channels can't be joined
+        let err = state.join_chat(2, 4).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        // single chats can't be left
+        let err = state.leave_chat(3, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::UpdateChatError(_)));
+
+        // nor can the owner leave their channel
+        let err = state.leave_chat(dev.id as _, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::UpdateChatError(_)));
+
+        let err = state.join_chat(100, 4).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        // users of other workspaces don't see the channel
+        let input = crate::CreateUser::new("other", "tom", "tom@other.org", "123456");
+        let tom = state.create_user(&input).await?;
+        let err = state.join_chat(dev.id as _, tom.id as _).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        let input = UpdateChat::new(None, &[tom.id], &[], None);
+        let err = state.update_chat(dev.id as _, input).await.unwrap_err();
+        assert!(matches!(err, AppError::UpdateChatError(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_members_should_have_roles() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateChat::new("dev", &[1, 2, 3], false);
+        let chat = state.create_chat(input, 1, 2).await?;
+        let members = state.fetch_chat_members(chat.id as _).await?;
+        let roles: Vec<_> = members.iter().map(|m| (m.user_id, m.role)).collect();
+        assert_eq!(
+            roles,
+            vec![
+                (1, ChatRole::Member),
+                (2, ChatRole::Owner),
+                (3, ChatRole::Member)
+            ]
+        );
+        assert!(members.iter().all(|m| !m.muted));
+
+        // members are removed with the chat
+        state.delete_chat(chat.id as _, 2).await?;
+        assert!(state.fetch_chat_members(chat.id as _).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_member_role_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        assert_eq!(state.fetch_chat_role(1, 1).await?, Some(ChatRole::Owner));
+        assert_eq!(state.fetch_chat_role(1, 2).await?, Some(ChatRole::Member));
+        assert_eq!(state.fetch_chat_role(3, 3).await?, None);
+
+        let input = UpdateMemberRole {
+            role: ChatRole::Admin,
+        };
+        let member = state.update_member_role(input, 1, 2).await?;
+        assert_eq!(member.role, ChatRole::Admin);
+        assert_eq!(state.fetch_chat_role(1, 2).await?, Some(ChatRole::Admin));
+
+        // admins can delete the chat
+        state.delete_chat(1, 2).await?;
+
+        // the owner's role is fixed
+        let input = UpdateMemberRole {
+            role: ChatRole::Member,
+        };
+        let err = state.update_member_role(input, 2, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::UpdateChatError(_)));
+
+        let input = UpdateMemberRole {
+            role: ChatRole::Owner,
+        };
+        let err = state.update_member_role(input, 2, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::UpdateChatError(_)));
+
+        let input = UpdateMemberRole {
+            role: ChatRole::Admin,
+        };
+        let err = state.update_member_role(input, 2, 5).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        // the workspace owner acts as owner of the chats they are in
+        state.update_workspace_owner(1, 3).await?;
+        assert_eq!(state.fetch_chat_role(2, 3).await?, Some(ChatRole::Owner));
         Ok(())
     }
 
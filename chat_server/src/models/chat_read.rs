@@ -0,0 +1,91 @@
+use chat_core::ChatRead;
+use serde::{Deserialize, Serialize};
+
+use crate::{AppError, AppState};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarkRead {
+    pub message_id: i64,
+}
+
+impl AppState {
+    // the read position only moves forward
+    pub async fn mark_read(
+        &self,
+        input: MarkRead,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<ChatRead, AppError> {
+        let exists = sqlx::query("SELECT 1 FROM messages WHERE id = $1 AND chat_id = $2")
+            .bind(input.message_id)
+            .bind(chat_id as i64)
+            .fetch_optional(&self.pool)
+            .await?;
+        if exists.is_none() {
+            return Err(AppError::NotFound(format!(
+                "message id {} in chat {chat_id}",
+                input.message_id
+            )));
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO chat_reads (chat_id, user_id, last_read_message_id)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (chat_id, user_id) DO UPDATE
+            SET last_read_message_id = EXCLUDED.last_read_message_id,
+                updated_at = CURRENT_TIMESTAMP
+            WHERE chat_reads.last_read_message_id < EXCLUDED.last_read_message_id
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(input.message_id)
+        .execute(&self.pool)
+        .await?;
+
+        let read = sqlx::query_as(
+            r#"
+            SELECT chat_id, user_id, last_read_message_id, updated_at
+            FROM chat_reads
+            WHERE chat_id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ListChat;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn mark_read_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let read = state.mark_read(MarkRead { message_id: 5 }, 1, 2).await?;
+        assert_eq!(read.last_read_message_id, 5);
+
+        // reading an older message doesn't move the position back
+        let read = state.mark_read(MarkRead { message_id: 3 }, 1, 2).await?;
+        assert_eq!(read.last_read_message_id, 5);
+
+        let chats = state.fetch_chats(ListChat::default(), 1, 2).await?.chats;
+        let chat = chats.iter().find(|c| c.chat.id == 1).unwrap();
+        // messages 6..10 minus the one sent by user 2
+        assert_eq!(chat.unread, 4);
+
+        // message 1 doesn't belong to chat 2
+        let err = state
+            .mark_read(MarkRead { message_id: 1 }, 2, 2)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+}
@@ -7,8 +7,8 @@ impl ChatFile {
     pub fn new(ws_id: u64, filename: &str, data: &[u8]) -> Self {
         let hash = Sha1::digest(data);
         Self {
-            ws_id: ws_id,
-            ext: filename.split('.').last().unwrap_or("txt").to_string(),
+            ws_id,
+            ext: filename.split('.').next_back().unwrap_or("txt").to_string(),
             hash: hex::encode(hash),
         }
     }
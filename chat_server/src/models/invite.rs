@@ -0,0 +1,215 @@
+use crate::{models::random_hex, AppError, AppState};
+use chat_core::WorkspaceInvite;
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, Transaction};
+
+const DEFAULT_INVITE_TTL: i64 = 7 * 24 * 3600;
+const MAX_INVITE_TTL: i64 = 30 * 24 * 3600;
+const MAX_INVITE_USES: i32 = 1000;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CreateInvite {
+    // bind the invite to an email, it can then be used only once
+    pub email: Option<String>,
+    pub max_uses: Option<i32>,
+    // seconds until the invite expires
+    pub expires_in: Option<i64>,
+}
+
+impl AppState {
+    pub async fn create_invite(
+        &self,
+        input: CreateInvite,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<WorkspaceInvite, AppError> {
+        self.verify_workspace_owner(ws_id, user_id).await?;
+
+        let max_uses = input.max_uses.unwrap_or(1);
+        if !(1..=MAX_INVITE_USES).contains(&max_uses) {
+            return Err(AppError::InviteError(format!(
+                "max_uses must be between 1 and {MAX_INVITE_USES}"
+            )));
+        }
+        if input.email.is_some() && max_uses > 1 {
+            return Err(AppError::InviteError(
+                "An email invite can only be used once".to_string(),
+            ));
+        }
+        let expires_in = input.expires_in.unwrap_or(DEFAULT_INVITE_TTL);
+        if !(1..=MAX_INVITE_TTL).contains(&expires_in) {
+            return Err(AppError::InviteError(format!(
+                "expires_in must be between 1 and {MAX_INVITE_TTL} seconds"
+            )));
+        }
+
+        let invite = sqlx::query_as(
+            r#"
+            INSERT INTO workspace_invites (ws_id, code, email, created_by, max_uses, expires_at)
+            VALUES ($1, $2, $3, $4, $5, CURRENT_TIMESTAMP + make_interval(secs => $6))
+            RETURNING id, ws_id, code, email, created_by, max_uses, uses, expires_at, created_at
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(random_hex::<16>())
+        .bind(&input.email)
+        .bind(user_id as i64)
+        .bind(max_uses)
+        .bind(expires_in as f64)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(invite)
+    }
+
+    pub async fn fetch_invites(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<Vec<WorkspaceInvite>, AppError> {
+        self.verify_workspace_owner(ws_id, user_id).await?;
+        let invites = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, code, email, created_by, max_uses, uses, expires_at, created_at
+            FROM workspace_invites
+            WHERE ws_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(ws_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(invites)
+    }
+
+    pub async fn revoke_invite(&self, id: u64, ws_id: u64, user_id: u64) -> Result<(), AppError> {
+        self.verify_workspace_owner(ws_id, user_id).await?;
+        let ret = sqlx::query("DELETE FROM workspace_invites WHERE id = $1 AND ws_id = $2")
+            .bind(id as i64)
+            .bind(ws_id as i64)
+            .execute(&self.pool)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("invite id {id}")));
+        }
+        Ok(())
+    }
+}
+
+// use up one redemption of the invite, called in the signup transaction so a
+// failed signup doesn't count
+pub(crate) async fn redeem_invite(
+    tx: &mut Transaction<'_, Postgres>,
+    code: &str,
+    email: &str,
+) -> Result<WorkspaceInvite, AppError> {
+    let invite = sqlx::query_as(
+        r#"
+        UPDATE workspace_invites
+        SET uses = uses + 1
+        WHERE code = $1
+          AND uses < max_uses
+          AND expires_at > CURRENT_TIMESTAMP
+          AND (email IS NULL OR lower(email) = lower($2))
+        RETURNING id, ws_id, code, email, created_by, max_uses, uses, expires_at, created_at
+        "#,
+    )
+    .bind(code)
+    .bind(email)
+    .fetch_optional(&mut **tx)
+    .await?;
+    invite.ok_or_else(|| AppError::InviteError("Invite is invalid or expired".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateUser;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn create_invite_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // only the workspace owner can invite
+        let err = state
+            .create_invite(CreateInvite::default(), 1, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        state.update_workspace_owner(1, 1).await?;
+        let invite = state.create_invite(CreateInvite::default(), 1, 1).await?;
+        assert_eq!(invite.ws_id, 1);
+        assert_eq!(invite.code.len(), 32);
+        assert_eq!(invite.max_uses, 1);
+        assert_eq!(invite.uses, 0);
+
+        let input = CreateInvite {
+            email: Some("tom@acme.org".to_string()),
+            max_uses: Some(2),
+            expires_in: None,
+        };
+        let err = state.create_invite(input, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::InviteError(_)));
+
+        let input = CreateInvite {
+            expires_in: Some(MAX_INVITE_TTL + 1),
+            ..Default::default()
+        };
+        let err = state.create_invite(input, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::InviteError(_)));
+
+        let invites = state.fetch_invites(1, 1).await?;
+        assert_eq!(invites, vec![invite.clone()]);
+
+        state.revoke_invite(invite.id as _, 1, 1).await?;
+        assert!(state.fetch_invites(1, 1).await?.is_empty());
+        let err = state.revoke_invite(invite.id as _, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signup_with_invite_should_respect_limits() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let input = CreateInvite {
+            max_uses: Some(2),
+            ..Default::default()
+        };
+        let invite = state.create_invite(input, 1, 1).await?;
+
+        let input = CreateUser::with_invite(&invite.code, "tom", "tom@acme.org", "123456");
+        let user = state.create_user(&input).await?;
+        assert_eq!(user.ws_id, 1);
+        let input = CreateUser::with_invite(&invite.code, "ann", "ann@acme.org", "123456");
+        state.create_user(&input).await?;
+
+        // usage limit reached
+        let input = CreateUser::with_invite(&invite.code, "joe", "joe@acme.org", "123456");
+        let err = state.create_user(&input).await.unwrap_err();
+        assert!(matches!(err, AppError::InviteError(_)));
+
+        // email invites only work for that email
+        let input = CreateInvite {
+            email: Some("Joe@acme.org".to_string()),
+            ..Default::default()
+        };
+        let invite = state.create_invite(input, 1, 1).await?;
+        let input = CreateUser::with_invite(&invite.code, "kim", "kim@acme.org", "123456");
+        let err = state.create_user(&input).await.unwrap_err();
+        assert!(matches!(err, AppError::InviteError(_)));
+        let input = CreateUser::with_invite(&invite.code, "joe", "joe@acme.org", "123456");
+        state.create_user(&input).await?;
+
+        // expired invites are rejected
+        let invite = state.create_invite(CreateInvite::default(), 1, 1).await?;
+        sqlx::query("UPDATE workspace_invites SET expires_at = CURRENT_TIMESTAMP WHERE id = $1")
+            .bind(invite.id)
+            .execute(&state.pool)
+            .await?;
+        let input = CreateUser::with_invite(&invite.code, "kim", "kim@acme.org", "123456");
+        let err = state.create_user(&input).await.unwrap_err();
+        assert!(matches!(err, AppError::InviteError(_)));
+        Ok(())
+    }
+}
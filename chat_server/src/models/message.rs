@@ -2,24 +2,63 @@ use std::str::FromStr;
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
 
-use crate::{AppError, AppState, ChatFile};
+use crate::{AppError, AppState, ChatFile, ReactionCount};
 
 use chat_core::Message;
+use chrono::{DateTime, Utc};
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateMessage {
     pub content: String,
     pub files: Vec<String>,
+    // the message being replied to, the thread root is derived from it
+    pub reply_to: Option<i64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EditMessage {
+    pub content: String,
+}
+
+// a message as listed in the chat, with the number of replies in its thread
+// and the reactions on it
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct MessageSummary {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub message: Message,
+    pub reply_count: i64,
+    #[sqlx(skip)]
+    pub reactions: Vec<ReactionCount>,
+}
+
+// at most one cursor can be given, no cursor means the latest messages.
+// `last_id` is kept as an alias of `before` for older clients
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ListMessage {
-    pub last_id: Option<u64>,
-    pub limit: u64,
+    #[serde(alias = "last_id")]
+    pub before: Option<u64>,
+    pub after: Option<u64>,
+    pub around: Option<u64>,
+    pub limit: Option<u64>,
 }
 
+// messages are newest first. `prev_cursor` is the `before` cursor of the
+// older page, `next_cursor` the `after` cursor of the newer page
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MessagePage {
+    pub messages: Vec<MessageSummary>,
+    pub has_more: bool,
+    pub prev_cursor: Option<i64>,
+    pub next_cursor: Option<i64>,
+}
+
+const DEFAULT_MESSAGE_LIMIT: u64 = 50;
+const MAX_MESSAGE_LIMIT: u64 = 200;
+
 #[allow(dead_code)]
 impl AppState {
     pub async fn create_message(
@@ -40,7 +79,7 @@ impl AppState {
         for s in &input.files {
             println!("s: {}", s);
             let file = ChatFile::from_str(s)?;
-            if !file.path(&base_dir).exists() {
+            if !file.path(base_dir).exists() {
                 return Err(AppError::CreateMessageError(format!(
                     "File {} does not exist",
                     s
@@ -48,46 +87,355 @@ impl AppState {
             }
         }
 
+        // replies to a reply stay in the thread of the top-level message
+        let thread_root_id = match input.reply_to {
+            Some(reply_to) => {
+                let parent: Option<(Option<i64>, Option<DateTime<Utc>>)> = sqlx::query_as(
+                    "SELECT thread_root_id, deleted_at FROM messages WHERE id = $1 AND chat_id = $2",
+                )
+                .bind(reply_to)
+                .bind(chat_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+                match parent {
+                    Some((root, None)) => Some(root.unwrap_or(reply_to)),
+                    _ => {
+                        return Err(AppError::CreateMessageError(format!(
+                            "Message {reply_to} does not exist"
+                        )))
+                    }
+                }
+            }
+            None => None,
+        };
+
         let messagge: Message = sqlx::query_as(
             r#"
-            INSERT INTO messages (chat_id, sender_id, content, files)
-            VALUES ($1, $2, $3, $4)
-            RETURNING id, chat_id, sender_id, content, files, created_at
+            INSERT INTO messages (chat_id, sender_id, content, files, reply_to, thread_root_id)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, chat_id, sender_id, content, files, created_at, edited_at, deleted_at,
+            reply_to, thread_root_id
             "#,
         )
         .bind(chat_id as i64)
         .bind(user_id as i64)
         .bind(input.content)
         .bind(&input.files)
+        .bind(input.reply_to)
+        .bind(thread_root_id)
         .fetch_one(&self.pool)
         .await?;
 
         Ok(messagge)
     }
 
+    // only the sender can edit, the old content goes to message_edits
+    pub async fn edit_message(
+        &self,
+        input: EditMessage,
+        chat_id: u64,
+        id: u64,
+        user_id: u64,
+    ) -> Result<Message, AppError> {
+        if input.content.is_empty() {
+            return Err(AppError::UpdateMessageError(
+                "Content cannot be empty".to_string(),
+            ));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        let message: Option<Message> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, content, files, created_at, edited_at, deleted_at,
+            reply_to, thread_root_id
+            FROM messages
+            WHERE id = $1 AND chat_id = $2
+            FOR UPDATE
+            "#,
+        )
+        .bind(id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(message) = message else {
+            return Err(AppError::NotFound(format!(
+                "message id {id} in chat {chat_id}"
+            )));
+        };
+        if message.sender_id != user_id as i64 {
+            return Err(AppError::PermissionDenied(format!(
+                "User {user_id} can't edit message {id}"
+            )));
+        }
+        if message.deleted_at.is_some() {
+            return Err(AppError::UpdateMessageError(format!(
+                "Message {id} is deleted"
+            )));
+        }
+        if message.content == input.content {
+            return Ok(message);
+        }
+
+        sqlx::query("INSERT INTO message_edits (message_id, content) VALUES ($1, $2)")
+            .bind(id as i64)
+            .bind(&message.content)
+            .execute(&mut *tx)
+            .await?;
+
+        let message = sqlx::query_as(
+            r#"
+            UPDATE messages
+            SET content = $1, edited_at = CURRENT_TIMESTAMP
+            WHERE id = $2
+            RETURNING id, chat_id, sender_id, content, files, created_at, edited_at, deleted_at,
+            reply_to, thread_root_id
+            "#,
+        )
+        .bind(input.content)
+        .bind(id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(message)
+    }
+
+    // the sender or the workspace owner can delete a message. It stays as a
+    // tombstone without content, files and reactions
+    pub async fn delete_message(
+        &self,
+        chat_id: u64,
+        id: u64,
+        user_id: u64,
+    ) -> Result<Message, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let message: Option<Message> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, content, files, created_at, edited_at, deleted_at,
+            reply_to, thread_root_id
+            FROM messages
+            WHERE id = $1 AND chat_id = $2
+            FOR UPDATE
+            "#,
+        )
+        .bind(id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(message) = message else {
+            return Err(AppError::NotFound(format!(
+                "message id {id} in chat {chat_id}"
+            )));
+        };
+
+        if message.sender_id != user_id as i64 {
+            // the owner of the chat's workspace moderates it, not the caller's
+            let (owner_id,): (i64,) = sqlx::query_as(
+                r#"
+                SELECT w.owner_id
+                FROM chats c
+                JOIN workspaces w ON w.id = c.ws_id
+                WHERE c.id = $1
+                "#,
+            )
+            .bind(chat_id as i64)
+            .fetch_one(&mut *tx)
+            .await?;
+            if owner_id != user_id as i64 {
+                return Err(AppError::PermissionDenied(format!(
+                    "User {user_id} can't delete message {id}"
+                )));
+            }
+        }
+        if message.deleted_at.is_some() {
+            return Ok(message);
+        }
+
+        let message = sqlx::query_as(
+            r#"
+            UPDATE messages
+            SET content = '', files = '{}', deleted_at = CURRENT_TIMESTAMP
+            WHERE id = $1
+            RETURNING id, chat_id, sender_id, content, files, created_at, edited_at, deleted_at,
+            reply_to, thread_root_id
+            "#,
+        )
+        .bind(id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        // the edit history would still hold the deleted text
+        sqlx::query("DELETE FROM message_edits WHERE message_id = $1")
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM message_reactions WHERE message_id = $1")
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(message)
+    }
+
     pub async fn list_messages(
         &self,
         input: ListMessage,
         chat_id: u64,
-    ) -> Result<Vec<Message>, AppError> {
-        let last_id = input.last_id.unwrap_or(i64::MAX as _);
+    ) -> Result<MessagePage, AppError> {
+        let limit = input
+            .limit
+            .unwrap_or(DEFAULT_MESSAGE_LIMIT)
+            .clamp(1, MAX_MESSAGE_LIMIT) as i64;
 
+        let mut messages = match (input.before, input.after, input.around) {
+            (before, None, None) => {
+                let before = before.map_or(i64::MAX, |id| id as i64);
+                self.fetch_top_level(chat_id, "m.id < $2", before, "DESC", limit)
+                    .await?
+            }
+            (None, Some(after), None) => {
+                let mut messages = self
+                    .fetch_top_level(chat_id, "m.id > $2", after as i64, "ASC", limit)
+                    .await?;
+                messages.reverse();
+                messages
+            }
+            // the around message itself is on the older half. A reply isn't
+            // listed here, the page is around its thread root instead
+            (None, None, Some(around)) => {
+                let root: Option<(i64,)> = sqlx::query_as(
+                    "SELECT COALESCE(thread_root_id, id) FROM messages WHERE id = $1 AND chat_id = $2",
+                )
+                .bind(around as i64)
+                .bind(chat_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+                let around = root.map_or(around as i64, |(id,)| id);
+                let newer = limit / 2;
+                let mut messages = self
+                    .fetch_top_level(chat_id, "m.id > $2", around, "ASC", newer)
+                    .await?;
+                messages.reverse();
+                let older = self
+                    .fetch_top_level(chat_id, "m.id <= $2", around, "DESC", limit - newer)
+                    .await?;
+                messages.extend(older);
+                messages
+            }
+            _ => {
+                return Err(AppError::ListMessageError(
+                    "Only one of before, after and around can be given".to_string(),
+                ))
+            }
+        };
+
+        let (has_older, has_newer) = match (messages.last(), messages.first()) {
+            (Some(oldest), Some(newest)) => (
+                self.has_top_level(chat_id, "id < $2", oldest.message.id)
+                    .await?,
+                self.has_top_level(chat_id, "id > $2", newest.message.id)
+                    .await?,
+            ),
+            _ => (false, false),
+        };
+        let has_more = match (input.after, input.around) {
+            (Some(_), _) => has_newer,
+            (_, Some(_)) => has_older || has_newer,
+            _ => has_older,
+        };
+        let prev_cursor = messages.last().filter(|_| has_older).map(|m| m.message.id);
+        let next_cursor = messages.first().filter(|_| has_newer).map(|m| m.message.id);
+
+        let ids: Vec<_> = messages.iter().map(|m| m.message.id).collect();
+        let mut counts = self.fetch_reaction_counts(&ids).await?;
+        for message in &mut messages {
+            let (mine, rest) = counts
+                .into_iter()
+                .partition(|c| c.message_id == message.message.id);
+            message.reactions = mine;
+            counts = rest;
+        }
+
+        Ok(MessagePage {
+            messages,
+            has_more,
+            prev_cursor,
+            next_cursor,
+        })
+    }
+
+    // replies are listed in their thread only
+    async fn fetch_top_level(
+        &self,
+        chat_id: u64,
+        cond: &str,
+        cursor: i64,
+        order: &str,
+        limit: i64,
+    ) -> Result<Vec<MessageSummary>, AppError> {
+        let messages = sqlx::query_as(&format!(
+            r#"
+        SELECT m.id, m.chat_id, m.sender_id, m.content, m.files, m.created_at, m.edited_at,
+            m.deleted_at, m.reply_to, m.thread_root_id,
+            (
+                SELECT COUNT(*)
+                FROM messages r
+                WHERE r.thread_root_id = m.id AND r.deleted_at IS NULL
+            ) AS reply_count
+        FROM messages m
+        WHERE m.chat_id = $1
+        AND m.thread_root_id IS NULL
+        AND {cond}
+        ORDER BY m.id {order}
+        LIMIT $3
+        "#
+        ))
+        .bind(chat_id as i64)
+        .bind(cursor)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(messages)
+    }
+
+    async fn has_top_level(&self, chat_id: u64, cond: &str, cursor: i64) -> Result<bool, AppError> {
+        let (exists,): (bool,) = sqlx::query_as(&format!(
+            r#"
+        SELECT EXISTS (
+            SELECT 1 FROM messages
+            WHERE chat_id = $1 AND thread_root_id IS NULL AND {cond}
+        )
+        "#
+        ))
+        .bind(chat_id as i64)
+        .bind(cursor)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(exists)
+    }
+
+    // the root message followed by its replies, oldest first
+    pub async fn list_thread(&self, chat_id: u64, root_id: u64) -> Result<Vec<Message>, AppError> {
         let messages: Vec<Message> = sqlx::query_as(
             r#"
-        SELECT id, chat_id, sender_id, content, files, created_at
+        SELECT id, chat_id, sender_id, content, files, created_at, edited_at, deleted_at,
+            reply_to, thread_root_id
         FROM messages
         WHERE chat_id = $1
-        AND id < $2
-        ORDER BY id DESC
-        LIMIT $3
+        AND (id = $2 AND thread_root_id IS NULL OR thread_root_id = $2)
+        ORDER BY id
         "#,
         )
         .bind(chat_id as i64)
-        .bind(last_id as i64)
-        .bind(input.limit as i64)
+        .bind(root_id as i64)
         .fetch_all(&self.pool)
         .await?;
 
+        if messages.first().map(|m| m.id) != Some(root_id as i64) {
+            return Err(AppError::NotFound(format!(
+                "thread {root_id} in chat {chat_id}"
+            )));
+        }
         Ok(messages)
     }
 }
@@ -95,6 +443,7 @@ impl AppState {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ReactionInput;
     use anyhow::Result;
 
     #[tokio::test]
@@ -103,6 +452,7 @@ mod tests {
         let input = CreateMessage {
             content: "Hello".to_string(),
             files: vec![],
+            reply_to: None,
         };
 
         let message = state
@@ -117,6 +467,7 @@ mod tests {
         let input = CreateMessage {
             content: "Hello".to_string(),
             files: vec!["1".to_string()],
+            reply_to: None,
         };
 
         let err = state.create_message(input, 1, 1).await.unwrap_err();
@@ -128,6 +479,7 @@ mod tests {
         let input = CreateMessage {
             content: "Hello".to_string(),
             files: vec![url],
+            reply_to: None,
         };
 
         let message = state
@@ -144,24 +496,252 @@ mod tests {
     async fn list_message_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
         let input = ListMessage {
-            last_id: None,
-            limit: 6,
+            limit: Some(6),
+            ..Default::default()
         };
 
-        let messages = state
+        let page = state
             .list_messages(input, 1)
             .await
             .expect("list messages failed");
 
-        assert_eq!(messages.len(), 6);
+        assert_eq!(page.messages.len(), 6);
+        assert!(page.has_more);
+        assert_eq!(page.prev_cursor, Some(5));
+        assert_eq!(page.next_cursor, None);
+
+        let input = ListMessage {
+            before: page.prev_cursor.map(|id| id as _),
+            limit: Some(6),
+            ..Default::default()
+        };
+        let page = state.list_messages(input, 1).await?;
+        assert_eq!(page.messages.len(), 4);
+        assert!(!page.has_more);
+        assert_eq!(page.prev_cursor, None);
+        assert_eq!(page.next_cursor, Some(4));
+
+        let input = ListMessage {
+            after: Some(4),
+            limit: Some(3),
+            ..Default::default()
+        };
+        let page = state.list_messages(input, 1).await?;
+        let ids: Vec<_> = page.messages.iter().map(|m| m.message.id).collect();
+        assert_eq!(ids, vec![7, 6, 5]);
+        assert!(page.has_more);
+        assert_eq!(page.next_cursor, Some(7));
+
+        let input = ListMessage {
+            around: Some(5),
+            limit: Some(4),
+            ..Default::default()
+        };
+        let page = state.list_messages(input, 1).await?;
+        let ids: Vec<_> = page.messages.iter().map(|m| m.message.id).collect();
+        assert_eq!(ids, vec![7, 6, 5, 4]);
+        assert!(page.has_more);
+
+        let input = ListMessage {
+            before: Some(5),
+            after: Some(1),
+            ..Default::default()
+        };
+        let err = state.list_messages(input, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::ListMessageError(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_message_around_reply_should_show_thread_root() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "Reply".to_string(),
+            files: vec![],
+            reply_to: Some(3),
+        };
+        let reply = state.create_message(input, 1, 1).await?;
+        let input = ListMessage {
+            around: Some(reply.id as _),
+            limit: Some(4),
+            ..Default::default()
+        };
+        let page = state.list_messages(input, 1).await?;
+        let ids: Vec<_> = page.messages.iter().map(|m| m.message.id).collect();
+        assert_eq!(ids, vec![5, 4, 3, 2]);
+        assert_eq!(page.messages[2].reply_count, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn edit_message_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = EditMessage {
+            content: "Hello, chat!".to_string(),
+        };
+        let message = state.edit_message(input, 1, 1, 1).await?;
+        assert_eq!(message.content, "Hello, chat!");
+        assert!(message.edited_at.is_some());
+
+        let edits: Vec<(String,)> =
+            sqlx::query_as("SELECT content FROM message_edits WHERE message_id = 1")
+                .fetch_all(&state.pool)
+                .await?;
+        assert_eq!(edits, vec![("Hello, world!".to_string(),)]);
+
+        // only the sender can edit
+        let input = EditMessage {
+            content: "Hi".to_string(),
+        };
+        let err = state.edit_message(input, 1, 1, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        // message must belong to the chat
+        let input = EditMessage {
+            content: "Hi".to_string(),
+        };
+        let err = state.edit_message(input, 2, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        let input = EditMessage {
+            content: "".to_string(),
+        };
+        let err = state.edit_message(input, 1, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::UpdateMessageError(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_message_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // user 2 is neither the sender nor the workspace owner
+        let err = state.delete_message(1, 1, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        let input = EditMessage {
+            content: "Hello, edited".to_string(),
+        };
+        state.edit_message(input, 1, 1, 1).await?;
+        let input = ReactionInput {
+            emoji: "👍".to_string(),
+        };
+        state.add_reaction(input, 1, 1, 2).await?;
+        let message = state.delete_message(1, 1, 1).await?;
+        assert!(message.deleted_at.is_some());
+        assert!(message.content.is_empty());
+        // the edit history goes with it
+        let (edits,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM message_edits WHERE message_id = 1")
+                .fetch_one(&state.pool)
+                .await?;
+        assert_eq!(edits, 0);
+        // and so do the reactions
+        assert!(state.fetch_reaction_counts(&[1]).await?.is_empty());
+
+        // owning another workspace doesn't help
+        let ws = state.create_workspace("other", 2).await?;
+        sqlx::query("INSERT INTO workspace_members (ws_id, user_id) VALUES ($1, 2)")
+            .bind(ws.id)
+            .execute(&state.pool)
+            .await?;
+        let err = state.delete_message(1, 3, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        // the chat's workspace owner can delete anyone's message
+        state.update_workspace_owner(1, 3).await?;
+        state.delete_message(1, 2, 3).await?;
+
+        // tombstones are still listed
+        let input = ListMessage {
+            limit: Some(10),
+            ..Default::default()
+        };
+        let messages = state.list_messages(input, 1).await?.messages;
+        assert_eq!(messages.len(), 10);
+        assert!(messages[9].message.deleted_at.is_some());
+
+        // deleted messages can't be edited
+        let input = EditMessage {
+            content: "Hi".to_string(),
+        };
+        let err = state.edit_message(input, 1, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::UpdateMessageError(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn thread_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "Reply".to_string(),
+            files: vec![],
+            reply_to: Some(3),
+        };
+        let reply = state.create_message(input, 1, 2).await?;
+        assert_eq!(reply.thread_root_id, Some(3));
+
+        // a reply to a reply joins the same thread
+        let input = CreateMessage {
+            content: "Reply to reply".to_string(),
+            files: vec![],
+            reply_to: Some(reply.id),
+        };
+        let message = state.create_message(input, 1, 1).await?;
+        assert_eq!(message.reply_to, Some(reply.id));
+        assert_eq!(message.thread_root_id, Some(3));
+
+        let thread = state.list_thread(1, 3).await?;
+        let ids: Vec<_> = thread.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![3, reply.id, message.id]);
+
+        // replies are not top-level messages
+        let input = ListMessage {
+            limit: Some(20),
+            ..Default::default()
+        };
+        let messages = state.list_messages(input, 1).await?.messages;
+        assert_eq!(messages.len(), 10);
+        let root = messages.iter().find(|m| m.message.id == 3).unwrap();
+        assert_eq!(root.reply_count, 2);
+
+        assert!(matches!(
+            state.list_thread(1, reply.id as _).await.unwrap_err(),
+            AppError::NotFound(_)
+        ));
+
+        // can't reply to a message in another chat
+        let input = CreateMessage {
+            content: "Reply".to_string(),
+            files: vec![],
+            reply_to: Some(3),
+        };
+        let err = state.create_message(input, 2, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::CreateMessageError(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_message_should_include_reactions() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        for (emoji, user_id) in [("👍", 1), ("👍", 2), ("🎉", 3)] {
+            let input = ReactionInput {
+                emoji: emoji.to_string(),
+            };
+            state.add_reaction(input, 1, 10, user_id).await?;
+        }
 
-        let last_id = messages.last().expect("last message should exists").id;
         let input = ListMessage {
-            last_id: Some(last_id as _),
-            limit: 6,
+            limit: Some(2),
+            ..Default::default()
         };
-        let messages = state.list_messages(input, 1).await?;
-        assert_eq!(messages.len(), 4);
+        let messages = state.list_messages(input, 1).await?.messages;
+        let reactions: Vec<_> = messages[0]
+            .reactions
+            .iter()
+            .map(|r| (r.emoji.as_str(), r.count))
+            .collect();
+        assert_eq!(reactions, vec![("👍", 2), ("🎉", 1)]);
+        assert!(messages[1].reactions.is_empty());
         Ok(())
     }
 
@@ -1,14 +1,35 @@
 mod chat;
+mod chat_read;
 mod file;
+mod invite;
 mod message;
+mod reaction;
+mod refresh_token;
+mod search;
 mod user;
 mod workspace;
 
+use argon2::password_hash::rand_core::{OsRng, RngCore};
 use serde::{Deserialize, Serialize};
 
-pub use chat::CreateChat;
+pub use chat::{
+    ChatPage, ChatSummary, CreateChat, ListChat, MessagePreview, UpdateChat, UpdateMemberRole,
+};
+pub use chat_read::MarkRead;
+pub use invite::CreateInvite;
 pub use message::*;
+pub use reaction::{ReactionCount, ReactionInput};
+pub use refresh_token::{RefreshInput, RefreshToken};
+pub use search::{SearchHit, SearchMessage};
 pub use user::{CreateUser, SigninUser};
+pub use workspace::{JoinWorkspace, TransferWorkspace, UpdateWorkspace};
+
+// N random bytes, hex encoded
+pub(crate) fn random_hex<const N: usize>() -> String {
+    let mut buf = [0u8; N];
+    OsRng.fill_bytes(&mut buf);
+    hex::encode(buf)
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatFile {
@@ -0,0 +1,156 @@
+use chat_core::Reaction;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::{AppError, AppState};
+
+const MAX_EMOJI_LEN: usize = 32;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReactionInput {
+    pub emoji: String,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct ReactionCount {
+    #[serde(skip)]
+    pub message_id: i64,
+    pub emoji: String,
+    pub count: i64,
+}
+
+impl AppState {
+    // reacting twice with the same emoji is a no-op
+    pub async fn add_reaction(
+        &self,
+        input: ReactionInput,
+        chat_id: u64,
+        id: u64,
+        user_id: u64,
+    ) -> Result<Vec<ReactionCount>, AppError> {
+        if input.emoji.is_empty() || input.emoji.chars().count() > MAX_EMOJI_LEN {
+            return Err(AppError::ReactionError(format!(
+                "Emoji must be 1 to {MAX_EMOJI_LEN} characters"
+            )));
+        }
+        let exists = sqlx::query(
+            "SELECT 1 FROM messages WHERE id = $1 AND chat_id = $2 AND deleted_at IS NULL",
+        )
+        .bind(id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        if exists.is_none() {
+            return Err(AppError::NotFound(format!(
+                "message id {id} in chat {chat_id}"
+            )));
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO message_reactions (message_id, user_id, emoji)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(id as i64)
+        .bind(user_id as i64)
+        .bind(&input.emoji)
+        .execute(&self.pool)
+        .await?;
+
+        self.fetch_reaction_counts(&[id as i64]).await
+    }
+
+    pub async fn remove_reaction(
+        &self,
+        input: ReactionInput,
+        chat_id: u64,
+        id: u64,
+        user_id: u64,
+    ) -> Result<Vec<ReactionCount>, AppError> {
+        let reaction: Option<Reaction> = sqlx::query_as(
+            r#"
+            DELETE FROM message_reactions r
+            USING messages m
+            WHERE r.message_id = m.id AND m.chat_id = $1
+            AND r.message_id = $2 AND r.user_id = $3 AND r.emoji = $4
+            RETURNING r.message_id, r.user_id, r.emoji, r.created_at
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(id as i64)
+        .bind(user_id as i64)
+        .bind(&input.emoji)
+        .fetch_optional(&self.pool)
+        .await?;
+        if reaction.is_none() {
+            return Err(AppError::NotFound(format!(
+                "reaction {} on message {id}",
+                input.emoji
+            )));
+        }
+
+        self.fetch_reaction_counts(&[id as i64]).await
+    }
+
+    // counts per emoji, in the order they were first used
+    pub async fn fetch_reaction_counts(
+        &self,
+        message_ids: &[i64],
+    ) -> Result<Vec<ReactionCount>, AppError> {
+        let counts = sqlx::query_as(
+            r#"
+            SELECT message_id, emoji, COUNT(*) AS count
+            FROM message_reactions
+            WHERE message_id = ANY($1)
+            GROUP BY message_id, emoji
+            ORDER BY message_id, MIN(created_at)
+            "#,
+        )
+        .bind(message_ids)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn reaction_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = ReactionInput {
+            emoji: "👍".to_string(),
+        };
+        state.add_reaction(input.clone(), 1, 1, 1).await?;
+        state.add_reaction(input.clone(), 1, 1, 1).await?;
+        let counts = state.add_reaction(input.clone(), 1, 1, 2).await?;
+        assert_eq!(counts.len(), 1);
+        assert_eq!(counts[0].count, 2);
+
+        let counts = state.remove_reaction(input.clone(), 1, 1, 1).await?;
+        assert_eq!(counts[0].count, 1);
+
+        // the reaction is gone already
+        let err = state
+            .remove_reaction(input.clone(), 1, 1, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        // message 1 doesn't belong to chat 2
+        let err = state.add_reaction(input, 2, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        let input = ReactionInput {
+            emoji: "".to_string(),
+        };
+        let err = state.add_reaction(input, 1, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::ReactionError(_)));
+        Ok(())
+    }
+}
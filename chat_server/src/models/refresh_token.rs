@@ -0,0 +1,334 @@
+use crate::{models::random_hex, AppError, AppState};
+use chat_core::{TokenRevocation, User, JWT_DUTARION};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgExecutor};
+use tracing::warn;
+
+const REFRESH_TOKEN_TTL: i64 = 30 * 24 * 3600;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshInput {
+    pub refresh_token: String,
+}
+
+// a refresh token and the session it belongs to, access tokens are signed for
+// the session so revoking it ends them too
+#[derive(Debug, Clone)]
+pub struct RefreshToken {
+    pub sid: String,
+    pub token: String,
+}
+
+#[derive(Debug, FromRow)]
+struct RefreshTokenRow {
+    id: i64,
+    user_id: i64,
+    ws_id: i64,
+    family: String,
+    expires_at: DateTime<Utc>,
+    used_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+impl AppState {
+    // a refresh token starting a new family, scoped like the user's access token
+    pub async fn create_refresh_token(&self, user: &User) -> Result<RefreshToken, AppError> {
+        let sid = random_hex::<16>();
+        let token = insert_refresh_token(&self.pool, user.id, user.ws_id, &sid).await?;
+        Ok(RefreshToken { sid, token })
+    }
+
+    // swap a refresh token for the next one of its family, and the user to
+    // sign the access token for
+    pub async fn rotate_refresh_token(
+        &self,
+        token: &str,
+    ) -> Result<(User, RefreshToken), AppError> {
+        let mut tx = self.pool.begin().await?;
+        let row: Option<RefreshTokenRow> = sqlx::query_as(
+            r#"
+            SELECT id, user_id, ws_id, family, expires_at, used_at, revoked_at
+            FROM refresh_tokens
+            WHERE token_hash = $1
+            FOR UPDATE
+            "#,
+        )
+        .bind(hash_token(token))
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(row) = row else {
+            return Err(AppError::RefreshTokenError("unknown token".to_string()));
+        };
+        if row.revoked_at.is_some() || row.expires_at <= Utc::now() {
+            return Err(AppError::RefreshTokenError(
+                "token expired or revoked".to_string(),
+            ));
+        }
+        if row.used_at.is_some() {
+            // a rotated token came back, whoever holds the family can't be trusted
+            warn!("refresh token reused, revoking family {}", row.family);
+            let revoked = revoke_families(&mut *tx, &[row.family]).await?;
+            tx.commit().await?;
+            self.cache_revocations(&revoked);
+            return Err(AppError::RefreshTokenError(
+                "token reused, session revoked".to_string(),
+            ));
+        }
+
+        sqlx::query("UPDATE refresh_tokens SET used_at = CURRENT_TIMESTAMP WHERE id = $1")
+            .bind(row.id)
+            .execute(&mut *tx)
+            .await?;
+
+        let user: Option<User> = sqlx::query_as(
+            r#"
+            SELECT u.id, wm.ws_id, u.fullname, u.email, u.created_at
+            FROM users u
+            JOIN workspace_members wm ON wm.user_id = u.id
+            WHERE u.id = $1 AND wm.ws_id = $2
+            "#,
+        )
+        .bind(row.user_id)
+        .bind(row.ws_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(user) = user else {
+            let revoked = revoke_families(&mut *tx, &[row.family]).await?;
+            tx.commit().await?;
+            self.cache_revocations(&revoked);
+            return Err(AppError::RefreshTokenError(format!(
+                "user {} left workspace {}",
+                row.user_id, row.ws_id
+            )));
+        };
+
+        let token = insert_refresh_token(&mut *tx, row.user_id, row.ws_id, &row.family).await?;
+        tx.commit().await?;
+        Ok((
+            user,
+            RefreshToken {
+                sid: row.family,
+                token,
+            },
+        ))
+    }
+
+    // logout ends the whole family, unknown tokens are ignored
+    pub async fn revoke_refresh_token(&self, token: &str) -> Result<(), AppError> {
+        let family: Option<(String,)> =
+            sqlx::query_as("SELECT family FROM refresh_tokens WHERE token_hash = $1")
+                .bind(hash_token(token))
+                .fetch_optional(&self.pool)
+                .await?;
+        if let Some((family,)) = family {
+            let revoked = revoke_families(&self.pool, &[family]).await?;
+            self.cache_revocations(&revoked);
+        }
+        Ok(())
+    }
+
+    // sign out everywhere, every session of the user ends
+    pub async fn revoke_user_sessions(&self, user_id: u64) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        let families = live_families(&mut *tx, user_id as _, None).await?;
+        let revoked = revoke_families(&mut *tx, &families).await?;
+        tx.commit().await?;
+        self.cache_revocations(&revoked);
+        Ok(())
+    }
+
+    // a single access token, e.g. one that leaked. Its session lives on
+    pub async fn revoke_access_token(&self, jti: &str) -> Result<(), AppError> {
+        let revocation: TokenRevocation = sqlx::query_as(
+            r#"
+            INSERT INTO token_revocations (jti, expires_at)
+            VALUES ($1, CURRENT_TIMESTAMP + make_interval(secs => $2))
+            RETURNING id, jti, sid, created_at, expires_at
+            "#,
+        )
+        .bind(jti)
+        .bind(JWT_DUTARION as f64)
+        .fetch_one(&self.pool)
+        .await?;
+        self.cache_revocations(&[revocation]);
+        Ok(())
+    }
+
+    // the servers listening for `token_revoked` catch up on their own, this one
+    // shouldn't accept the tokens in the meantime
+    pub(crate) fn cache_revocations(&self, revocations: &[TokenRevocation]) {
+        for revocation in revocations {
+            self.revocations.insert(revocation);
+        }
+    }
+}
+
+async fn insert_refresh_token(
+    executor: impl PgExecutor<'_>,
+    user_id: i64,
+    ws_id: i64,
+    family: &str,
+) -> Result<String, AppError> {
+    let token = random_hex::<32>();
+    sqlx::query(
+        r#"
+        INSERT INTO refresh_tokens (user_id, ws_id, family, token_hash, expires_at)
+        VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP + make_interval(secs => $5))
+        "#,
+    )
+    .bind(user_id)
+    .bind(ws_id)
+    .bind(family)
+    .bind(hash_token(&token))
+    .bind(REFRESH_TOKEN_TTL as f64)
+    .execute(executor)
+    .await?;
+    Ok(token)
+}
+
+// families of the user that can still be refreshed, optionally scoped to a workspace
+pub(crate) async fn live_families(
+    executor: impl PgExecutor<'_>,
+    user_id: i64,
+    ws_id: Option<i64>,
+) -> Result<Vec<String>, AppError> {
+    let families: Vec<(String,)> = sqlx::query_as(
+        r#"
+        SELECT DISTINCT family
+        FROM refresh_tokens
+        WHERE user_id = $1 AND ($2::bigint IS NULL OR ws_id = $2)
+          AND revoked_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+        "#,
+    )
+    .bind(user_id)
+    .bind(ws_id)
+    .fetch_all(executor)
+    .await?;
+    Ok(families.into_iter().map(|(family,)| family).collect())
+}
+
+// revoke the families and every access token signed for them. The
+// revocations go to the cache once the transaction is committed
+pub(crate) async fn revoke_families(
+    executor: impl PgExecutor<'_>,
+    families: &[String],
+) -> Result<Vec<TokenRevocation>, AppError> {
+    let revoked = sqlx::query_as(
+        r#"
+        WITH revoked AS (
+            UPDATE refresh_tokens
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE family = ANY($1) AND revoked_at IS NULL
+            RETURNING family
+        )
+        INSERT INTO token_revocations (sid, expires_at)
+        SELECT DISTINCT family, CURRENT_TIMESTAMP + make_interval(secs => $2)
+        FROM revoked
+        RETURNING id, jti, sid, created_at, expires_at
+        "#,
+    )
+    .bind(families)
+    .bind(JWT_DUTARION as f64)
+    .fetch_all(executor)
+    .await?;
+    Ok(revoked)
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use chat_core::{RevocationList, TokenSession};
+
+    #[tokio::test]
+    async fn rotate_refresh_token_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        let token = state.create_refresh_token(&user).await?;
+        assert_eq!(token.token.len(), 64);
+
+        let (ret, next) = state.rotate_refresh_token(&token.token).await?;
+        assert_eq!(ret, user);
+        assert_ne!(next.token, token.token);
+        assert_eq!(next.sid, token.sid);
+        let (_, next) = state.rotate_refresh_token(&next.token).await?;
+
+        let err = state.rotate_refresh_token("foo").await.unwrap_err();
+        assert!(matches!(err, AppError::RefreshTokenError(_)));
+
+        // reusing a rotated token revokes the whole family, and the session
+        let session = TokenSession {
+            jti: "jti".to_string(),
+            sid: token.sid.clone(),
+        };
+        assert!(!state.revocations.is_revoked(&session));
+        let err = state.rotate_refresh_token(&token.token).await.unwrap_err();
+        assert!(matches!(err, AppError::RefreshTokenError(_)));
+        let err = state.rotate_refresh_token(&next.token).await.unwrap_err();
+        assert!(matches!(err, AppError::RefreshTokenError(_)));
+        assert!(state.revocations.is_revoked(&session));
+
+        // other signins are not affected
+        let other = state.create_refresh_token(&user).await?;
+        assert_ne!(other.sid, token.sid);
+        state.rotate_refresh_token(&other.token).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn revoke_refresh_token_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        let token = state.create_refresh_token(&user).await?;
+        let (_, next) = state.rotate_refresh_token(&token.token).await?;
+
+        // any token of the family logs it out
+        state.revoke_refresh_token(&token.token).await?;
+        let err = state.rotate_refresh_token(&next.token).await.unwrap_err();
+        assert!(matches!(err, AppError::RefreshTokenError(_)));
+        state.revoke_refresh_token("foo").await?;
+
+        // the revocation reaches the other servers through the database
+        let revocations = RevocationList::default();
+        assert_eq!(revocations.load(&state.pool).await?, 1);
+
+        // expired tokens are rejected
+        let token = state.create_refresh_token(&user).await?.token;
+        sqlx::query(
+            "UPDATE refresh_tokens SET expires_at = CURRENT_TIMESTAMP WHERE token_hash = $1",
+        )
+        .bind(hash_token(&token))
+        .execute(&state.pool)
+        .await?;
+        let err = state.rotate_refresh_token(&token).await.unwrap_err();
+        assert!(matches!(err, AppError::RefreshTokenError(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn revoke_access_token_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        let token = state.create_refresh_token(&user).await?;
+        let (_, revoked) = state.dk.verify(&state.ek.sign(user.clone(), &token.sid)?)?;
+        let (_, kept) = state.dk.verify(&state.ek.sign(user, &token.sid)?)?;
+
+        state.revoke_access_token(&revoked.jti).await?;
+        assert!(state.revocations.is_revoked(&revoked));
+        // other tokens of the session still work, and it can be refreshed
+        assert!(!state.revocations.is_revoked(&kept));
+        state.rotate_refresh_token(&token.token).await?;
+
+        let revocations = RevocationList::default();
+        assert_eq!(revocations.load(&state.pool).await?, 1);
+        assert!(revocations.is_revoked(&revoked));
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::{AppError, AppState};
+
+const DEFAULT_SEARCH_LIMIT: u64 = 20;
+const MAX_SEARCH_LIMIT: u64 = 100;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchMessage {
+    pub q: String,
+    pub chat_id: Option<i64>,
+    pub sender_id: Option<i64>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub has_files: Option<bool>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct SearchHit {
+    pub message_id: i64,
+    pub chat_id: i64,
+    pub chat_name: Option<String>,
+    pub sender_id: i64,
+    pub sender_name: String,
+    // HTML, the content is escaped and matched words are wrapped in <b></b>
+    pub snippet: String,
+    pub rank: f32,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AppState {
+    // only messages in the user's chats of the workspace are searched
+    pub async fn search_messages(
+        &self,
+        input: SearchMessage,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<Vec<SearchHit>, AppError> {
+        let q = input.q.trim();
+        if q.is_empty() {
+            return Err(AppError::SearchError("Query cannot be empty".to_string()));
+        }
+        let limit = input
+            .limit
+            .unwrap_or(DEFAULT_SEARCH_LIMIT)
+            .clamp(1, MAX_SEARCH_LIMIT);
+
+        let hits = sqlx::query_as(
+            r#"
+            SELECT m.id AS message_id, m.chat_id, c.name AS chat_name, m.sender_id,
+                u.fullname AS sender_name,
+                ts_headline('simple', html_escape(m.content), query, 'MaxFragments=2') AS snippet,
+                ts_rank(to_tsvector('simple', m.content), query) AS rank,
+                m.created_at
+            FROM messages m
+            JOIN chats c ON c.id = m.chat_id
+            JOIN chat_members cm ON cm.chat_id = c.id AND cm.user_id = $3
+            JOIN users u ON u.id = m.sender_id,
+            websearch_to_tsquery('simple', $1) query
+            -- matches messages_content_search_idx
+            WHERE to_tsvector('simple', m.content) @@ query
+            AND c.ws_id = $2
+            AND m.deleted_at IS NULL
+            AND ($4::bigint IS NULL OR m.chat_id = $4)
+            AND ($5::bigint IS NULL OR m.sender_id = $5)
+            AND ($6::timestamptz IS NULL OR m.created_at >= $6)
+            AND ($7::timestamptz IS NULL OR m.created_at < $7)
+            AND ($8::boolean IS NULL OR (cardinality(m.files) > 0) = $8)
+            ORDER BY rank DESC, m.id DESC
+            LIMIT $9
+            "#,
+        )
+        .bind(q)
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .bind(input.chat_id)
+        .bind(input.sender_id)
+        .bind(input.from)
+        .bind(input.to)
+        .bind(input.has_files)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CreateMessage, EditMessage};
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn search_messages_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = SearchMessage {
+            q: "world".to_string(),
+            ..Default::default()
+        };
+        let hits = state.search_messages(input, 1, 1).await?;
+        assert_eq!(hits.len(), 4);
+        assert_eq!(hits[0].sender_name, "hp");
+        assert_eq!(hits[0].chat_name.as_deref(), Some("general"));
+        assert!(hits[0].snippet.contains("<b>world</b>"));
+
+        let input = SearchMessage {
+            q: "there".to_string(),
+            sender_id: Some(1),
+            ..Default::default()
+        };
+        assert!(state.search_messages(input, 1, 1).await?.is_empty());
+
+        let input = SearchMessage {
+            q: "world".to_string(),
+            has_files: Some(true),
+            ..Default::default()
+        };
+        assert!(state.search_messages(input, 1, 1).await?.is_empty());
+
+        // user 2 isn't a member of chat 4
+        let input = SearchMessage {
+            q: "world".to_string(),
+            chat_id: Some(4),
+            ..Default::default()
+        };
+        assert!(state.search_messages(input, 1, 2).await?.is_empty());
+
+        let input = SearchMessage {
+            q: " ".to_string(),
+            ..Default::default()
+        };
+        let err = state.search_messages(input, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::SearchError(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_snippet_should_be_escaped() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: r#"<img src=x onerror="alert('hi')"> & payload"#.to_string(),
+            files: vec![],
+            reply_to: None,
+        };
+        state.create_message(input, 1, 1).await?;
+
+        let input = SearchMessage {
+            q: "payload".to_string(),
+            ..Default::default()
+        };
+        let hits = state.search_messages(input, 1, 1).await?;
+        assert_eq!(hits.len(), 1);
+        let snippet = &hits[0].snippet;
+        assert!(snippet.contains("<b>payload</b>"));
+        assert!(snippet.contains("&quot;alert(&#39;hi&#39;)&quot;&gt; &amp;"));
+        // the markers are the only markup left
+        let text = snippet.replace("<b>", "").replace("</b>", "");
+        assert!(!text.contains(['<', '>', '"', '\'']));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn long_message_should_be_sent_and_found() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // the notification payload of a ~4KB message stays under pg_notify's limit
+        let content: String = (0..500).map(|i| format!("word{i} ")).collect();
+        let input = CreateMessage {
+            content: content.clone(),
+            files: vec![],
+            reply_to: None,
+        };
+        let message = state.create_message(input, 1, 1).await?;
+        let input = EditMessage {
+            content: format!("{content}consectetur"),
+        };
+        state.edit_message(input, 1, message.id as _, 1).await?;
+
+        let input = SearchMessage {
+            q: "consectetur".to_string(),
+            ..Default::default()
+        };
+        let hits = state.search_messages(input, 1, 1).await?;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].message_id, message.id);
+        Ok(())
+    }
+}
@@ -1,19 +1,25 @@
-use crate::{AppError, AppState};
+use crate::{
+    models::{invite::redeem_invite, workspace::validate_workspace_name},
+    AppError, AppState,
+};
 use anyhow::Result;
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
     Argon2, PasswordHash, PasswordVerifier,
 };
-use chat_core::{ChatUser, User};
+use chat_core::{ChatUser, Presence, User, WorkSpace};
 use serde::{Deserialize, Serialize};
 use std::mem;
 
+// without an invite the user creates and owns a new workspace, its name is
+// required then
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateUser {
     pub fullname: String,
     pub email: String,
-    pub workspace: String,
+    pub workspace: Option<String>,
     pub password: String,
+    pub invite: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -69,7 +75,6 @@ impl AppState {
     }
 
     // Create a new user
-    // TODO: use transaction for workspace creation and user creation
     pub async fn create_user(&self, input: &CreateUser) -> Result<User, AppError> {
         // check if email exists
         let user = self.find_user_by_email(&input.email).await?;
@@ -77,13 +82,47 @@ impl AppState {
             return Err(AppError::EmailAlreadyExists(input.email.clone()));
         }
 
-        // check if workspace exists
-        let ws = match self.find_workspace_by_name(&input.workspace).await? {
-            Some(ws) => ws,
-            None => self.create_workspace(&input.workspace, 0).await?,
+        let workspace = match (&input.invite, &input.workspace) {
+            (Some(_), _) => None,
+            (None, Some(name)) => {
+                Some(validate_workspace_name(name).map_err(AppError::CreateUserError)?)
+            }
+            (None, None) => {
+                return Err(AppError::CreateUserError(
+                    "Workspace name is required without an invite".to_string(),
+                ))
+            }
         };
 
         let password_hash = hash_password(&input.password)?;
+        let mut tx = self.pool.begin().await?;
+        let ws_id = match (&input.invite, workspace) {
+            (Some(code), _) => redeem_invite(&mut tx, code, &input.email).await?.ws_id,
+            (None, name) => {
+                // existing workspaces can only be joined with an invite
+                let ws: Option<WorkSpace> = sqlx::query_as(
+                    r#"
+                    INSERT INTO workspaces (name, owner_id)
+                    VALUES ($1, 0)
+                    ON CONFLICT (name) DO NOTHING
+                    RETURNING id, name, owner_id, created_at
+                    "#,
+                )
+                .bind(name)
+                .fetch_optional(&mut *tx)
+                .await?;
+                match ws {
+                    Some(ws) => ws.id,
+                    None => {
+                        return Err(AppError::WorkspaceAlreadyExists(format!(
+                            "{}, ask its owner for an invite",
+                            name.unwrap_or_default()
+                        )))
+                    }
+                }
+            }
+        };
+
         let user: User = sqlx::query_as(
             r#"
         INSERT INTO users (ws_id, email, fullname, password_hash)
@@ -91,17 +130,27 @@ impl AppState {
         RETURNING id, ws_id, fullname, email, created_at
         "#,
         )
-        .bind(&ws.id)
+        .bind(ws_id)
         .bind(&input.email)
         .bind(&input.fullname)
         .bind(password_hash)
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await?;
 
-        if ws.owner_id == 0 {
-            self.update_workspace_owner(ws.id as _, user.id as _)
+        sqlx::query("INSERT INTO workspace_members (ws_id, user_id) VALUES ($1, $2)")
+            .bind(ws_id)
+            .bind(user.id)
+            .execute(&mut *tx)
+            .await?;
+
+        if input.invite.is_none() {
+            sqlx::query("UPDATE workspaces SET owner_id = $1 WHERE id = $2")
+                .bind(user.id)
+                .bind(ws_id)
+                .execute(&mut *tx)
                 .await?;
         }
+        tx.commit().await?;
 
         Ok(user)
     }
@@ -114,7 +163,27 @@ impl AppState {
             WHERE id = ANY($1)
             "#,
         )
-        .bind(&ids)
+        .bind(ids)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(users)
+    }
+
+    pub async fn fetch_workspace_users_by_ids(
+        &self,
+        ws_id: u64,
+        ids: &[i64],
+    ) -> Result<Vec<ChatUser>, AppError> {
+        let users = sqlx::query_as(
+            r#"
+            SELECT u.id, u.fullname, u.email
+            FROM users u
+            JOIN workspace_members wm ON wm.user_id = u.id
+            WHERE wm.ws_id = $1 AND u.id = ANY($2)
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(ids)
         .fetch_all(&self.pool)
         .await?;
         Ok(users)
@@ -123,9 +192,11 @@ impl AppState {
     pub async fn fetch_chat_users(&self, ws_id: u64) -> Result<Vec<ChatUser>, AppError> {
         let users = sqlx::query_as(
             r#"
-            SELECT id, fullname, email
-            FROM users
-            WHERE ws_id = $1
+            SELECT u.id, u.fullname, u.email
+            FROM users u
+            JOIN workspace_members wm ON wm.user_id = u.id
+            WHERE wm.ws_id = $1
+            ORDER BY u.id
             "#,
         )
         .bind(ws_id as i64)
@@ -134,6 +205,25 @@ impl AppState {
 
         Ok(users)
     }
+
+    // users without a presence row have never been online
+    pub async fn fetch_presence(&self, ws_id: u64) -> Result<Vec<Presence>, AppError> {
+        let presence = sqlx::query_as(
+            r#"
+            SELECT u.id AS user_id, COALESCE(p.status, 'offline') AS status, p.updated_at
+            FROM users u
+            JOIN workspace_members wm ON wm.user_id = u.id
+            LEFT JOIN user_presence p ON p.user_id = u.id
+            WHERE wm.ws_id = $1
+            ORDER BY u.id
+            "#,
+        )
+        .bind(ws_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(presence)
+    }
 }
 
 fn hash_password(password: &str) -> Result<String, AppError> {
@@ -164,9 +254,18 @@ impl CreateUser {
     pub fn new(workspace: &str, fullname: &str, email: &str, password: &str) -> Self {
         Self {
             fullname: fullname.to_string(),
-            workspace: workspace.to_string(),
+            workspace: Some(workspace.to_string()),
             email: email.to_string(),
             password: password.to_string(),
+            invite: None,
+        }
+    }
+
+    pub fn with_invite(code: &str, fullname: &str, email: &str, password: &str) -> Self {
+        Self {
+            workspace: None,
+            invite: Some(code.to_string()),
+            ..Self::new("", fullname, email, password)
         }
     }
 }
@@ -185,6 +284,7 @@ impl SigninUser {
 mod tests {
     use super::*;
     use anyhow::Result;
+    use chat_core::PresenceStatus;
 
     #[test]
     fn hash_password_should_work() -> Result<()> {
@@ -205,12 +305,35 @@ mod tests {
         let name = "HP";
         let password = "123456";
         let user = state
-            .create_user(&CreateUser::new("none", name, email, password))
+            .create_user(&CreateUser::new("hp", name, email, password))
             .await?;
         assert_eq!(user.email, email);
         assert_eq!(user.fullname, name);
         assert!(user.id > 0);
 
+        // the user owns the new workspace
+        let ws = state.find_workspace_by_id(user.ws_id as _).await?.unwrap();
+        assert_eq!(ws.name, "hp");
+        assert_eq!(ws.owner_id, user.id);
+
+        // existing workspaces need an invite, and nothing is created
+        let input = CreateUser::new("acme", "tom", "tom@acme.org", password);
+        let err = state.create_user(&input).await.unwrap_err();
+        assert!(matches!(err, AppError::WorkspaceAlreadyExists(_)));
+        assert!(state.find_user_by_email("tom@acme.org").await?.is_none());
+
+        // a new workspace needs a valid name
+        let mut input = CreateUser::new(" ", "tom", "tom@acme.org", password);
+        for name in [None, Some(" ".to_string()), Some("a".repeat(33))] {
+            input.workspace = name;
+            let err = state.create_user(&input).await.unwrap_err();
+            assert!(matches!(err, AppError::CreateUserError(_)));
+        }
+        input.workspace = Some(" tom ".to_string());
+        let user = state.create_user(&input).await?;
+        let ws = state.find_workspace_by_id(user.ws_id as _).await?.unwrap();
+        assert_eq!(ws.name, "tom");
+
         let user = state.find_user_by_email(email).await?;
         assert!(user.is_some());
         let user = user.unwrap();
@@ -222,12 +345,27 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn fetch_presence_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        sqlx::query("INSERT INTO user_presence (user_id, status) VALUES (1, 'away')")
+            .execute(&state.pool)
+            .await?;
+
+        let presence = state.fetch_presence(1).await?;
+        assert_eq!(presence.len(), 5);
+        assert_eq!(presence[0].status, PresenceStatus::Away);
+        assert_eq!(presence[1].status, PresenceStatus::Offline);
+        assert!(presence[1].updated_at.is_none());
+        Ok(())
+    }
+
     // find user by id test
     #[tokio::test]
     async fn find_user_by_id_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
         let user = state.find_user_by_id(1).await?;
-        assert_eq!(user.is_some(), true);
+        assert!(user.is_some());
         let user = user.unwrap();
         assert_eq!(user.id, 1);
         Ok(())
@@ -1,73 +1,332 @@
-use crate::{AppError, AppState};
+use crate::{
+    models::{
+        invite::redeem_invite,
+        refresh_token::{live_families, revoke_families},
+    },
+    AppError, AppState,
+};
 
 use chat_core::WorkSpace;
+use serde::{Deserialize, Serialize};
+
+// users removed from their last workspace are parked in the placeholder workspace
+const NO_WORKSPACE: i64 = 0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateWorkspace {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferWorkspace {
+    pub owner_id: i64,
+}
+
+// an existing user joins another workspace with an invite code
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JoinWorkspace {
+    pub invite: String,
+}
 
 impl AppState {
-    pub async fn create_workspace(&self, name: &str, user_id: u64) -> Result<WorkSpace, AppError> {
+    pub async fn find_workspace_by_id(&self, id: u64) -> Result<Option<WorkSpace>, AppError> {
         let ws = sqlx::query_as(
             r#"
-            INSERT INTO workspaces (name, owner_id)
-            VALUES ($1, $2)
-            RETURNING id, name, owner_id, created_at
+            SELECT id, name, owner_id, created_at
+            FROM workspaces
+            WHERE id = $1
             "#,
         )
-        .bind(name)
-        .bind(user_id as i64)
-        .fetch_one(&self.pool)
+        .bind(id as i64)
+        .fetch_optional(&self.pool)
         .await?;
         Ok(ws)
     }
 
-    pub async fn find_workspace_by_name(&self, name: &str) -> Result<Option<WorkSpace>, AppError> {
+    pub async fn update_workspace_owner(
+        &self,
+        id: u64,
+        owner_id: u64,
+    ) -> Result<WorkSpace, AppError> {
+        // the new owner must be a member of the workspace
         let ws = sqlx::query_as(
             r#"
-            SELECT id, name, owner_id, created_at
-            FROM workspaces
-            WHERE name = $1
+            UPDATE workspaces
+            SET owner_id = $1
+            WHERE id = $2
+              AND EXISTS (SELECT 1 FROM workspace_members WHERE ws_id = $2 AND user_id = $1)
+            RETURNING id, name, owner_id, created_at
             "#,
         )
-        .bind(name)
-        .fetch_optional(&self.pool)
+        .bind(owner_id as i64)
+        .bind(id as i64)
+        .fetch_one(&self.pool)
         .await?;
         Ok(ws)
     }
 
-    #[allow(unused)]
-    pub async fn find_workspace_by_id(&self, id: u64) -> Result<Option<WorkSpace>, AppError> {
-        let ws = sqlx::query_as(
+    pub async fn fetch_user_workspaces(&self, user_id: u64) -> Result<Vec<WorkSpace>, AppError> {
+        let workspaces = sqlx::query_as(
             r#"
-            SELECT id, name, owner_id, created_at
-            FROM workspaces
-            WHERE id = $1
+            SELECT w.id, w.name, w.owner_id, w.created_at
+            FROM workspaces w
+            JOIN workspace_members wm ON wm.ws_id = w.id
+            WHERE wm.user_id = $1
+            ORDER BY wm.joined_at, w.id
             "#,
         )
-        .bind(id as i64)
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(workspaces)
+    }
+
+    pub async fn is_workspace_member(&self, ws_id: u64, user_id: u64) -> Result<bool, AppError> {
+        let is_member = sqlx::query(
+            r#"
+            SELECT 1
+            FROM workspace_members
+            WHERE ws_id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
         .fetch_optional(&self.pool)
         .await?;
+        Ok(is_member.is_some())
+    }
+
+    // the invite is only used up if the user wasn't a member yet
+    pub async fn join_workspace(
+        &self,
+        input: JoinWorkspace,
+        user_id: u64,
+        email: &str,
+    ) -> Result<WorkSpace, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let invite = redeem_invite(&mut tx, &input.invite, email).await?;
+        let ret = sqlx::query(
+            r#"
+            INSERT INTO workspace_members (ws_id, user_id)
+            VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(invite.ws_id)
+        .bind(user_id as i64)
+        .execute(&mut *tx)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::UpdateWorkspaceError(format!(
+                "User {user_id} is already a member of workspace {}",
+                invite.ws_id
+            )));
+        }
+        tx.commit().await?;
+
+        match self.find_workspace_by_id(invite.ws_id as _).await? {
+            Some(ws) => Ok(ws),
+            None => Err(AppError::NotFound(format!("workspace id {}", invite.ws_id))),
+        }
+    }
+
+    // the workspace, if the user is its owner
+    pub async fn verify_workspace_owner(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<WorkSpace, AppError> {
+        let Some(ws) = self.find_workspace_by_id(ws_id).await? else {
+            return Err(AppError::NotFound(format!("workspace id {ws_id}")));
+        };
+        if ws.owner_id != user_id as i64 {
+            return Err(AppError::PermissionDenied(format!(
+                "User {user_id} is not the owner of workspace {ws_id}"
+            )));
+        }
         Ok(ws)
     }
 
-    pub async fn update_workspace_owner(
+    pub async fn rename_workspace(
         &self,
-        id: u64,
-        owner_id: u64,
+        input: UpdateWorkspace,
+        ws_id: u64,
+        user_id: u64,
     ) -> Result<WorkSpace, AppError> {
-        // update owner_id in two cases 1) owner_id = 0 3) owner's ws_id = id
-        let ws = sqlx::query_as(
+        self.verify_workspace_owner(ws_id, user_id).await?;
+        let name = validate_workspace_name(&input.name).map_err(AppError::UpdateWorkspaceError)?;
+
+        let ret = sqlx::query_as(
             r#"
             UPDATE workspaces
-            SET owner_id = $1
-            WHERE id = $2 and (SELECT ws_id FROM users WHERE id = $1) = $2
+            SET name = $1
+            WHERE id = $2
             RETURNING id, name, owner_id, created_at
             "#,
         )
-        .bind(owner_id as i64)
-        .bind(id as i64)
+        .bind(name)
+        .bind(ws_id as i64)
+        .fetch_one(&self.pool)
+        .await;
+        match ret {
+            Ok(ws) => Ok(ws),
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                Err(AppError::WorkspaceAlreadyExists(name.to_string()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn transfer_workspace(
+        &self,
+        input: TransferWorkspace,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<WorkSpace, AppError> {
+        self.verify_workspace_owner(ws_id, user_id).await?;
+        let users = self
+            .fetch_workspace_users_by_ids(ws_id, &[input.owner_id])
+            .await?;
+        if users.is_empty() {
+            return Err(AppError::NotFound(format!(
+                "user id {} in workspace {ws_id}",
+                input.owner_id
+            )));
+        }
+        self.update_workspace_owner(ws_id, input.owner_id as _)
+            .await
+    }
+
+    // the member leaves every chat of the workspace, chats they owned go to
+    // the longest-standing remaining member. Chats and their history are kept
+    pub async fn remove_workspace_member(
+        &self,
+        ws_id: u64,
+        member_id: u64,
+        user_id: u64,
+    ) -> Result<(), AppError> {
+        let ws = self.verify_workspace_owner(ws_id, user_id).await?;
+        if ws.owner_id == member_id as i64 {
+            return Err(AppError::UpdateWorkspaceError(
+                "Workspace owner can't be removed".to_string(),
+            ));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        let ret = sqlx::query("DELETE FROM workspace_members WHERE ws_id = $1 AND user_id = $2")
+            .bind(ws_id as i64)
+            .bind(member_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!(
+                "user id {member_id} in workspace {ws_id}"
+            )));
+        }
+
+        // signin falls back to the next workspace the user is in
+        sqlx::query(
+            r#"
+            UPDATE users
+            SET ws_id = COALESCE(
+                (SELECT ws_id FROM workspace_members WHERE user_id = $1 ORDER BY joined_at, ws_id LIMIT 1),
+                $2)
+            WHERE id = $1 AND ws_id = $3
+            "#,
+        )
+        .bind(member_id as i64)
+        .bind(NO_WORKSPACE)
+        .bind(ws_id as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM chat_members
+            WHERE user_id = $1 AND chat_id IN (SELECT id FROM chats WHERE ws_id = $2)
+            "#,
+        )
+        .bind(member_id as i64)
+        .bind(ws_id as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            WITH heirs AS (
+                SELECT DISTINCT ON (cm.chat_id) cm.chat_id, cm.user_id
+                FROM chat_members cm
+                JOIN chats c ON c.id = cm.chat_id
+                WHERE c.ws_id = $1 AND c.owner_id = $2
+                ORDER BY cm.chat_id, cm.joined_at, cm.user_id
+            ), owners AS (
+                UPDATE chats c
+                SET owner_id = h.user_id
+                FROM heirs h
+                WHERE c.id = h.chat_id
+            )
+            UPDATE chat_members cm
+            SET role = 'owner'
+            FROM heirs h
+            WHERE cm.chat_id = h.chat_id AND cm.user_id = h.user_id
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(member_id as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        // the member's tokens for this workspace stop working right away
+        let families = live_families(&mut *tx, member_id as _, Some(ws_id as _)).await?;
+        let revoked = revoke_families(&mut *tx, &families).await?;
+        tx.commit().await?;
+        self.cache_revocations(&revoked);
+        Ok(())
+    }
+}
+
+// surrounding whitespace doesn't count, the trimmed name is stored
+pub(crate) fn validate_workspace_name(name: &str) -> Result<&str, String> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > 32 {
+        return Err("Workspace name must be 1 to 32 characters".to_string());
+    }
+    Ok(name)
+}
+
+// workspaces are created on signup, these are for tests only
+#[cfg(test)]
+impl AppState {
+    pub async fn create_workspace(&self, name: &str, user_id: u64) -> Result<WorkSpace, AppError> {
+        let ws = sqlx::query_as(
+            r#"
+            INSERT INTO workspaces (name, owner_id)
+            VALUES ($1, $2)
+            RETURNING id, name, owner_id, created_at
+            "#,
+        )
+        .bind(name)
+        .bind(user_id as i64)
         .fetch_one(&self.pool)
         .await?;
         Ok(ws)
     }
+
+    pub async fn find_workspace_by_name(&self, name: &str) -> Result<Option<WorkSpace>, AppError> {
+        let ws = sqlx::query_as(
+            r#"
+            SELECT id, name, owner_id, created_at
+            FROM workspaces
+            WHERE name = $1
+            "#,
+        )
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(ws)
+    }
 }
+
 #[cfg(test)]
 mod tests {
     use anyhow::{Ok, Result};
@@ -79,11 +338,15 @@ mod tests {
     async fn workspace_should_create_and_set_owner() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
         let ws = state.create_workspace("test", 0).await.unwrap();
-        let input = CreateUser::new(&ws.name, "hp1", "hp@none.com", "123");
+        let input = CreateUser::new("hp1", "hp1", "hp@none.com", "123");
         let user = state.create_user(&input).await.unwrap();
+        sqlx::query("INSERT INTO workspace_members (ws_id, user_id) VALUES ($1, $2)")
+            .bind(ws.id)
+            .bind(user.id)
+            .execute(&state.pool)
+            .await?;
 
         assert_eq!(ws.name, "test");
-        assert_eq!(user.ws_id, ws.id);
 
         let ws = state
             .update_workspace_owner(ws.id as _, user.id as u64)
@@ -93,6 +356,175 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn rename_workspace_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = UpdateWorkspace {
+            name: "acme2".to_string(),
+        };
+        let err = state
+            .rename_workspace(input.clone(), 1, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        state.update_workspace_owner(1, 1).await?;
+        let ws = state.rename_workspace(input, 1, 1).await?;
+        assert_eq!(ws.name, "acme2");
+
+        let input = UpdateWorkspace {
+            name: "foo".to_string(),
+        };
+        let err = state.rename_workspace(input, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::WorkspaceAlreadyExists(_)));
+
+        let input = UpdateWorkspace {
+            name: "a".repeat(33),
+        };
+        let err = state.rename_workspace(input, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::UpdateWorkspaceError(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn transfer_workspace_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let input = TransferWorkspace { owner_id: 2 };
+        let ws = state.transfer_workspace(input.clone(), 1, 1).await?;
+        assert_eq!(ws.owner_id, 2);
+
+        // user 1 is no longer the owner
+        let err = state.transfer_workspace(input, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        let input = TransferWorkspace { owner_id: 10 };
+        let err = state.transfer_workspace(input, 1, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn remove_workspace_member_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 2).await?;
+        let err = state.remove_workspace_member(1, 2, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::UpdateWorkspaceError(_)));
+
+        // user 1 owns every chat in the fixtures
+        let input = crate::CreateChat::new("", &[1, 2, 4], false);
+        let group = state.create_chat(input, 1, 1).await?;
+        assert_eq!(group.r#type, chat_core::ChatType::Group);
+        let input = crate::CreateMessage {
+            content: "bye".to_string(),
+            files: vec![],
+            reply_to: None,
+        };
+        state.create_message(input, 3, 2).await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        let token = state.create_refresh_token(&user).await?;
+        state.remove_workspace_member(1, 1, 2).await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        assert_eq!(user.ws_id, NO_WORKSPACE);
+        // the member's sessions in the workspace are revoked
+        let session = chat_core::TokenSession {
+            jti: "jti".to_string(),
+            sid: token.sid,
+        };
+        assert!(state.revocations.is_revoked(&session));
+        assert_eq!(state.fetch_chat_users(1).await?.len(), 4);
+
+        let chat = state.get_chat_by_id(1).await?.unwrap();
+        assert!(!chat.members.contains(&1));
+        assert_eq!(chat.owner_id, 2);
+        let chat = state.get_chat_by_id(2).await?.unwrap();
+        assert_eq!(chat.members, vec![2, 3]);
+        assert_eq!(chat.owner_id, 2);
+        assert_eq!(
+            state.fetch_chat_role(2, 2).await?,
+            Some(chat_core::ChatRole::Owner)
+        );
+        // chats left with a single member are kept with their history
+        let chat = state.get_chat_by_id(3).await?.unwrap();
+        assert_eq!(chat.members, vec![2]);
+        assert_eq!(chat.owner_id, 2);
+        let chat = state.get_chat_by_id(4).await?.unwrap();
+        assert_eq!(chat.members, vec![3]);
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM messages WHERE chat_id = 3")
+            .fetch_one(&state.pool)
+            .await?;
+        assert_eq!(count, 1);
+        // and chats keep their type
+        let chat = state.get_chat_by_id(group.id as _).await?.unwrap();
+        assert_eq!(chat.members, vec![2, 4]);
+        assert_eq!(chat.r#type, chat_core::ChatType::Group);
+
+        let err = state.remove_workspace_member(1, 1, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn join_workspace_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateUser::new("contractors", "tom", "tom@none.com", "123456");
+        let tom = state.create_user(&input).await?;
+
+        state.update_workspace_owner(1, 1).await?;
+        let invite = state
+            .create_invite(crate::CreateInvite::default(), 1, 1)
+            .await?;
+        let input = JoinWorkspace {
+            invite: invite.code.clone(),
+        };
+        let ws = state
+            .join_workspace(input.clone(), tom.id as _, &tom.email)
+            .await?;
+        assert_eq!(ws.id, 1);
+        assert!(state.is_workspace_member(1, tom.id as _).await?);
+        assert_eq!(state.fetch_chat_users(1).await?.len(), 6);
+
+        let workspaces = state.fetch_user_workspaces(tom.id as _).await?;
+        let ids: Vec<_> = workspaces.iter().map(|ws| ws.id).collect();
+        assert_eq!(ids, vec![tom.ws_id, 1]);
+
+        // the invite is used up
+        let err = state
+            .join_workspace(input, 1, "hp@acme.org")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::InviteError(_)));
+
+        // members don't use up invites
+        let invite = state
+            .create_invite(crate::CreateInvite::default(), 1, 1)
+            .await?;
+        let input = JoinWorkspace {
+            invite: invite.code,
+        };
+        let err = state
+            .join_workspace(input, tom.id as _, &tom.email)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::UpdateWorkspaceError(_)));
+        assert_eq!(state.fetch_invites(1, 1).await?[1].uses, 0);
+
+        // user 2 joins tom's workspace, then is removed from acme
+        let invite = state
+            .create_invite(crate::CreateInvite::default(), tom.ws_id as _, tom.id as _)
+            .await?;
+        let input = JoinWorkspace {
+            invite: invite.code,
+        };
+        state.join_workspace(input, 2, "zsr@acme.org").await?;
+        state.remove_workspace_member(1, 2, 1).await?;
+        assert!(!state.is_workspace_member(1, 2).await?);
+        // signin now goes to the remaining workspace
+        let user = state.find_user_by_id(2).await?.unwrap();
+        assert_eq!(user.ws_id, tom.ws_id);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn workspace_should_find_by_name() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+use chat_core::TOKEN_REVOKED;
+use sqlx::postgres::PgListener;
+use tracing::{info, warn};
+
+use crate::AppState;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+// keep the revocation list in sync with the other servers. A lost connection
+// reloads the whole list, revocations may have been missed in between
+pub(crate) fn setup_revocation_listener(state: AppState) {
+    tokio::spawn(async move {
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            match run_listener(&state, &mut backoff).await {
+                Ok(()) => warn!("revocation listener connection lost"),
+                Err(e) => warn!("revocation listener failed: {}", e),
+            }
+            info!("reconnecting revocation listener in {:?}", backoff);
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    });
+}
+
+async fn run_listener(state: &AppState, backoff: &mut Duration) -> anyhow::Result<()> {
+    let mut listener = PgListener::connect(&state.config.server.db_url).await?;
+    listener.listen(TOKEN_REVOKED).await?;
+    // load after LISTEN, so nothing falls in between
+    let len = state.revocations.load(&state.pool).await?;
+    info!("revocation listener connected, {} revocations loaded", len);
+    *backoff = MIN_BACKOFF;
+
+    while let Some(notif) = listener.try_recv().await? {
+        if let Err(e) = state.revocations.apply(notif.payload()) {
+            warn!("Failed to load token revocation: {}", e);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- record who created the chat, existing chats are owned by the super user
+ALTER TABLE chats
+    ADD COLUMN owner_id bigint NOT NULL DEFAULT 0 REFERENCES users(id);
@@ -0,0 +1,43 @@
+-- Add migration script here
+-- add payload version to notifications, see chat_core::NOTIFICATION_VERSION
+CREATE OR REPLACE FUNCTION add_to_chat()
+RETURNS TRIGGER AS $$
+BEGIN
+    RAISE NOTICE 'add_to_chat: %', NEW;
+    PERFORM pg_notify('chat_updated', json_build_object(
+        'version', 1,
+        'op', TG_OP,
+        'old', OLD,
+        'new', NEW
+    )::TEXT);
+    RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    RAISE NOTICE 'add_to_message: %', NEW;
+    -- select chat with chat_id in NEW
+    SELECT
+      members INTO USERS
+    FROM
+      chats
+    WHERE
+      id = NEW.chat_id;
+    PERFORM
+      pg_notify('chat_message_created', json_build_object(
+        'version', 1,
+        'message', NEW,
+        'members', USERS
+      )::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
@@ -0,0 +1,20 @@
+-- Add migration script here
+-- track chat changes so notify_server can catch up after losing its listener
+ALTER TABLE chats
+    ADD COLUMN updated_at timestamptz DEFAULT CURRENT_TIMESTAMP;
+
+CREATE INDEX IF NOT EXISTS chats_updated_at_index ON chats(updated_at);
+
+CREATE OR REPLACE FUNCTION set_chat_updated_at()
+RETURNS TRIGGER AS $$
+BEGIN
+    NEW.updated_at = CURRENT_TIMESTAMP;
+    RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER set_chat_updated_at_trigger
+BEFORE UPDATE ON chats
+FOR EACH ROW
+EXECUTE FUNCTION set_chat_updated_at();
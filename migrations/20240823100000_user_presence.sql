@@ -0,0 +1,9 @@
+-- Add migration script here
+-- presence of users, maintained by notify_server
+CREATE TYPE presence_status AS ENUM('online', 'away', 'offline');
+
+CREATE TABLE IF NOT EXISTS user_presence (
+    user_id bigint PRIMARY KEY REFERENCES users(id),
+    status presence_status NOT NULL,
+    updated_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,39 @@
+-- Add migration script here
+-- the last message each member has read in a chat
+CREATE TABLE IF NOT EXISTS chat_reads (
+    chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    user_id bigint NOT NULL REFERENCES users(id),
+    last_read_message_id bigint NOT NULL,
+    updated_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (chat_id, user_id)
+);
+
+-- if a user read new messages, notify the chat members
+CREATE OR REPLACE FUNCTION add_to_chat_read()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+BEGIN
+  RAISE NOTICE 'add_to_chat_read: %', NEW;
+  SELECT
+    members INTO USERS
+  FROM
+    chats
+  WHERE
+    id = NEW.chat_id;
+  PERFORM
+    pg_notify('chat_read', json_build_object(
+      'version', 1,
+      'read', NEW,
+      'members', USERS
+    )::text);
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER add_to_chat_read_trigger
+AFTER INSERT OR UPDATE ON chat_reads
+FOR EACH ROW
+EXECUTE FUNCTION add_to_chat_read();
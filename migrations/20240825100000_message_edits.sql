@@ -0,0 +1,53 @@
+-- Add migration script here
+ALTER TABLE messages ADD COLUMN edited_at timestamptz;
+
+-- previous contents of an edited message
+CREATE TABLE IF NOT EXISTS message_edits (
+    id bigserial PRIMARY KEY,
+    message_id bigint NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    content text NOT NULL,
+    edited_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS message_edits_message_id_idx ON message_edits(message_id);
+
+-- notify on edits as well as new messages
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+BEGIN
+  RAISE NOTICE 'add_to_message: %', NEW;
+  SELECT
+    members INTO USERS
+  FROM
+    chats
+  WHERE
+    id = NEW.chat_id;
+  IF TG_OP = 'INSERT' THEN
+    PERFORM
+      pg_notify('chat_message_created', json_build_object(
+        'version', 1,
+        'message', NEW,
+        'members', USERS
+      )::text);
+  ELSIF TG_OP = 'UPDATE' AND OLD.content IS DISTINCT FROM NEW.content THEN
+    PERFORM
+      pg_notify('chat_message_updated', json_build_object(
+        'version', 1,
+        'message', NEW,
+        'members', USERS
+      )::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS add_to_message_trigger ON messages;
+
+CREATE TRIGGER add_to_message_trigger
+AFTER INSERT OR UPDATE ON messages
+FOR EACH ROW
+EXECUTE FUNCTION add_to_message();
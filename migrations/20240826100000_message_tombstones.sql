@@ -0,0 +1,43 @@
+-- Add migration script here
+-- deleted messages are kept as tombstones so pagination stays stable
+ALTER TABLE messages ADD COLUMN deleted_at timestamptz;
+
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+BEGIN
+  RAISE NOTICE 'add_to_message: %', NEW;
+  SELECT
+    members INTO USERS
+  FROM
+    chats
+  WHERE
+    id = NEW.chat_id;
+  IF TG_OP = 'INSERT' THEN
+    PERFORM
+      pg_notify('chat_message_created', json_build_object(
+        'version', 1,
+        'message', NEW,
+        'members', USERS
+      )::text);
+  ELSIF TG_OP = 'UPDATE' AND OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+    PERFORM
+      pg_notify('chat_message_deleted', json_build_object(
+        'version', 1,
+        'message', NEW,
+        'members', USERS
+      )::text);
+  ELSIF TG_OP = 'UPDATE' AND OLD.content IS DISTINCT FROM NEW.content THEN
+    PERFORM
+      pg_notify('chat_message_updated', json_build_object(
+        'version', 1,
+        'message', NEW,
+        'members', USERS
+      )::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
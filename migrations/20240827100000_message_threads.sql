@@ -0,0 +1,48 @@
+-- Add migration script here
+-- reply_to is the message being answered, thread_root_id the top-level
+-- message of the thread
+ALTER TABLE messages ADD COLUMN reply_to bigint REFERENCES messages(id);
+ALTER TABLE messages ADD COLUMN thread_root_id bigint REFERENCES messages(id);
+
+CREATE INDEX IF NOT EXISTS messages_thread_root_id_idx ON messages(thread_root_id, id);
+
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+BEGIN
+  RAISE NOTICE 'add_to_message: %', NEW;
+  SELECT
+    members INTO USERS
+  FROM
+    chats
+  WHERE
+    id = NEW.chat_id;
+  IF TG_OP = 'INSERT' THEN
+    PERFORM
+      pg_notify('chat_message_created', json_build_object(
+        'version', 1,
+        'message', NEW,
+        'members', USERS,
+        'thread_root_id', NEW.thread_root_id
+      )::text);
+  ELSIF TG_OP = 'UPDATE' AND OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+    PERFORM
+      pg_notify('chat_message_deleted', json_build_object(
+        'version', 1,
+        'message', NEW,
+        'members', USERS
+      )::text);
+  ELSIF TG_OP = 'UPDATE' AND OLD.content IS DISTINCT FROM NEW.content THEN
+    PERFORM
+      pg_notify('chat_message_updated', json_build_object(
+        'version', 1,
+        'message', NEW,
+        'members', USERS
+      )::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
@@ -0,0 +1,6 @@
+-- Add migration script here
+-- 'simple' config, since messages aren't in a single language
+ALTER TABLE messages
+    ADD COLUMN content_tsv tsvector GENERATED ALWAYS AS (to_tsvector('simple', content)) STORED;
+
+CREATE INDEX IF NOT EXISTS messages_content_tsv_idx ON messages USING GIN(content_tsv);
@@ -0,0 +1,257 @@
+-- Add migration script here
+-- chat membership moves from chats.members to chat_members. Chat.members is
+-- still returned by the API, built with chat_member_ids()
+CREATE TYPE chat_role AS ENUM(
+  'owner',
+  'admin',
+  'member'
+);
+
+CREATE TABLE IF NOT EXISTS chat_members (
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id),
+  role chat_role NOT NULL DEFAULT 'member',
+  joined_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  muted boolean NOT NULL DEFAULT FALSE,
+  PRIMARY KEY (chat_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS chat_members_user_id_index ON chat_members(user_id);
+
+INSERT INTO chat_members(chat_id, user_id, role, joined_at)
+SELECT
+  c.id,
+  u.id,
+  CASE WHEN u.id = c.owner_id THEN
+    'owner'::chat_role
+  ELSE
+    'member'::chat_role
+  END,
+  c.created_at
+FROM
+  chats c
+  JOIN users u ON u.id = ANY (c.members)
+ON CONFLICT
+  DO NOTHING;
+
+-- member ids in the order they joined
+CREATE OR REPLACE FUNCTION chat_member_ids(bigint)
+  RETURNS bigint[]
+  AS $$
+  SELECT
+    COALESCE(array_agg(user_id ORDER BY joined_at, user_id), '{}')
+  FROM
+    chat_members
+  WHERE
+    chat_id = $1
+$$
+LANGUAGE sql
+STABLE;
+
+-- chat rows only notify on rename/type change and delete, membership
+-- changes are notified by chat_members triggers below
+CREATE OR REPLACE FUNCTION add_to_chat()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF TG_OP = 'UPDATE' AND (OLD.name IS DISTINCT FROM NEW.name OR OLD.type IS DISTINCT FROM NEW.type) THEN
+    RAISE NOTICE 'add_to_chat: %', NEW;
+    PERFORM
+      pg_notify('chat_updated', json_build_object(
+        'version', 2,
+        'op', TG_OP,
+        'old', to_jsonb(OLD) || jsonb_build_object('members', chat_member_ids(OLD.id)),
+        'new', to_jsonb(NEW) || jsonb_build_object('members', chat_member_ids(NEW.id)),
+        'added', '{}'::bigint[],
+        'removed', '{}'::bigint[]
+      )::text);
+  ELSIF TG_OP = 'DELETE' THEN
+    RAISE NOTICE 'add_to_chat: %', OLD;
+    PERFORM
+      pg_notify('chat_updated', json_build_object(
+        'version', 2,
+        'op', TG_OP,
+        'old', to_jsonb(OLD) || jsonb_build_object('members', chat_member_ids(OLD.id)),
+        'new', NULL,
+        'added', '{}'::bigint[],
+        'removed', chat_member_ids(OLD.id)
+      )::text);
+  END IF;
+  RETURN COALESCE(NEW, OLD);
+END;
+$$
+LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS add_to_chat_trigger ON chats;
+
+CREATE TRIGGER add_to_chat_trigger
+  AFTER UPDATE ON chats
+  FOR EACH ROW
+  EXECUTE FUNCTION add_to_chat();
+
+-- members are removed by the cascade after the chat row is gone, so read
+-- them before the delete
+CREATE TRIGGER remove_chat_trigger
+  BEFORE DELETE ON chats
+  FOR EACH ROW
+  EXECUTE FUNCTION add_to_chat();
+
+-- one notification per chat and statement. A chat without members before
+-- the statement is a new chat
+CREATE OR REPLACE FUNCTION add_to_chat_members()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  CID bigint;
+  CHANGED bigint[];
+  CHAT jsonb;
+  NEW_MEMBERS bigint[];
+  OLD_MEMBERS bigint[];
+BEGIN
+  FOR CID,
+  CHANGED IN
+  SELECT
+    r.chat_id,
+    array_agg(r.user_id ORDER BY r.user_id)
+  FROM
+    changed_rows r
+  GROUP BY
+    r.chat_id LOOP
+      SELECT
+        to_jsonb(c) INTO CHAT
+      FROM
+        chats c
+      WHERE
+        c.id = CID;
+      -- the chat itself is being deleted
+      CONTINUE
+      WHEN CHAT IS NULL;
+      NEW_MEMBERS := chat_member_ids(CID);
+      IF TG_OP = 'INSERT' THEN
+        OLD_MEMBERS := ARRAY (
+          SELECT
+            unnest(NEW_MEMBERS)
+          EXCEPT
+          SELECT
+            unnest(CHANGED));
+      ELSE
+        OLD_MEMBERS := NEW_MEMBERS || CHANGED;
+      END IF;
+      -- so notify_server can catch up on membership changes
+      UPDATE
+        chats
+      SET
+        updated_at = CURRENT_TIMESTAMP
+      WHERE
+        id = CID;
+      PERFORM
+        pg_notify('chat_updated', json_build_object(
+          'version', 2,
+          'op', CASE WHEN cardinality(OLD_MEMBERS) = 0 THEN 'INSERT' ELSE 'UPDATE' END,
+          'old', CASE WHEN cardinality(OLD_MEMBERS) = 0 THEN NULL ELSE CHAT || jsonb_build_object('members', OLD_MEMBERS) END,
+          'new', CHAT || jsonb_build_object('members', NEW_MEMBERS),
+          'added', CASE WHEN TG_OP = 'INSERT' THEN CHANGED ELSE '{}'::bigint[] END,
+          'removed', CASE WHEN TG_OP = 'DELETE' THEN CHANGED ELSE '{}'::bigint[] END
+        )::text);
+    END LOOP;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER add_to_chat_members_trigger
+  AFTER INSERT ON chat_members REFERENCING NEW TABLE AS changed_rows
+  FOR EACH STATEMENT
+  EXECUTE FUNCTION add_to_chat_members();
+
+CREATE TRIGGER remove_from_chat_members_trigger
+  AFTER DELETE ON chat_members REFERENCING OLD TABLE AS changed_rows
+  FOR EACH STATEMENT
+  EXECUTE FUNCTION add_to_chat_members();
+
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+BEGIN
+  RAISE NOTICE 'add_to_message: %', NEW;
+  USERS := chat_member_ids(NEW.chat_id);
+  IF TG_OP = 'INSERT' THEN
+    PERFORM
+      pg_notify('chat_message_created', json_build_object(
+        'version', 2,
+        'message', NEW,
+        'members', USERS,
+        'thread_root_id', NEW.thread_root_id
+      )::text);
+  ELSIF TG_OP = 'UPDATE' AND OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+    PERFORM
+      pg_notify('chat_message_deleted', json_build_object(
+        'version', 2,
+        'message', NEW,
+        'members', USERS
+      )::text);
+  ELSIF TG_OP = 'UPDATE' AND OLD.content IS DISTINCT FROM NEW.content THEN
+    PERFORM
+      pg_notify('chat_message_updated', json_build_object(
+        'version', 2,
+        'message', NEW,
+        'members', USERS
+      )::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION add_to_chat_read()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  RAISE NOTICE 'add_to_chat_read: %', NEW;
+  PERFORM
+    pg_notify('chat_read', json_build_object(
+      'version', 2,
+      'read', NEW,
+      'members', chat_member_ids(NEW.chat_id)
+    )::text);
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION add_to_message_reaction()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  REACTION message_reactions;
+  CID bigint;
+BEGIN
+  IF TG_OP = 'DELETE' THEN
+    REACTION := OLD;
+  ELSE
+    REACTION := NEW;
+  END IF;
+  RAISE NOTICE 'add_to_message_reaction: %', REACTION;
+  SELECT
+    m.chat_id INTO CID
+  FROM
+    messages m
+  WHERE
+    m.id = REACTION.message_id;
+  PERFORM
+    pg_notify('message_reaction_changed', json_build_object(
+      'version', 2,
+      'op', TG_OP,
+      'chat_id', CID,
+      'reaction', REACTION,
+      'members', chat_member_ids(CID)
+    )::text);
+  RETURN REACTION;
+END;
+$$
+LANGUAGE plpgsql;
+
+ALTER TABLE chats
+  DROP COLUMN members;
@@ -0,0 +1,16 @@
+-- Add migration script here
+-- signing up into an existing workspace needs an invite. An invite with an
+-- email can only be redeemed by that email
+CREATE TABLE IF NOT EXISTS workspace_invites (
+    id bigserial PRIMARY KEY,
+    ws_id bigint NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    code varchar(64) NOT NULL UNIQUE,
+    email varchar(64),
+    created_by bigint NOT NULL REFERENCES users(id),
+    max_uses integer NOT NULL DEFAULT 1 CHECK (max_uses > 0),
+    uses integer NOT NULL DEFAULT 0,
+    expires_at timestamptz NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS workspace_invites_ws_id_index ON workspace_invites(ws_id);
@@ -0,0 +1,23 @@
+-- Add migration script here
+-- a user can be a member of several workspaces. users.ws_id stays as the
+-- workspace the signin token is scoped to
+CREATE TABLE IF NOT EXISTS workspace_members (
+    ws_id bigint NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    user_id bigint NOT NULL REFERENCES users(id),
+    joined_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (ws_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS workspace_members_user_id_index ON workspace_members(user_id);
+
+INSERT INTO workspace_members(ws_id, user_id, joined_at)
+SELECT
+  ws_id,
+  id,
+  COALESCE(created_at, CURRENT_TIMESTAMP)
+FROM
+  users
+WHERE
+  ws_id <> 0
+ON CONFLICT
+  DO NOTHING;
@@ -0,0 +1,20 @@
+-- Add migration script here
+-- opaque refresh tokens, only their sha256 is stored. Every refresh rotates
+-- the token within its family, a family is one signin
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    id bigserial PRIMARY KEY,
+    user_id bigint NOT NULL REFERENCES users(id),
+    -- workspace the issued access tokens are scoped to
+    ws_id bigint NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    family varchar(32) NOT NULL,
+    token_hash char(64) NOT NULL UNIQUE,
+    expires_at timestamptz NOT NULL,
+    -- set when the token is rotated, using it again revokes the family
+    used_at timestamptz,
+    revoked_at timestamptz,
+    created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_family_index ON refresh_tokens(family);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_user_id_index ON refresh_tokens(user_id);
@@ -0,0 +1,34 @@
+-- Add migration script here
+-- revoked access tokens, by token id or session (refresh token family).
+-- A row is only needed until every token it applies to has expired
+CREATE TABLE IF NOT EXISTS token_revocations (
+    id bigserial PRIMARY KEY,
+    jti varchar(64),
+    sid varchar(32),
+    created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at timestamptz NOT NULL,
+    CHECK (num_nonnulls(jti, sid) = 1)
+);
+
+CREATE INDEX IF NOT EXISTS token_revocations_expires_at_index ON token_revocations(expires_at);
+
+-- servers keep the revocations in memory, tell them about new ones
+CREATE OR REPLACE FUNCTION add_to_token_revocation()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  RAISE NOTICE 'add_to_token_revocation: %', NEW;
+  PERFORM
+    pg_notify('token_revoked', json_build_object(
+      'version', 2,
+      'revocation', NEW
+    )::text);
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER add_to_token_revocation_trigger
+  AFTER INSERT ON token_revocations
+  FOR EACH ROW
+  EXECUTE FUNCTION add_to_token_revocation();
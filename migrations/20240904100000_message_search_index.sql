@@ -0,0 +1,9 @@
+-- Add migration script here
+-- the stored tsvector was part of NEW, so every message notification carried
+-- it and long messages went over pg_notify's 8000 byte payload limit. Index
+-- the expression instead, searches must use the same expression
+DROP INDEX IF EXISTS messages_content_tsv_idx;
+
+ALTER TABLE messages DROP COLUMN IF EXISTS content_tsv;
+
+CREATE INDEX IF NOT EXISTS messages_content_search_idx ON messages USING GIN(to_tsvector('simple', content));
@@ -0,0 +1,11 @@
+-- Add migration script here
+-- search snippets are HTML, ts_headline only adds the <b> markers so the
+-- content has to be escaped first
+CREATE OR REPLACE FUNCTION html_escape(content text)
+  RETURNS text
+  AS $$
+  SELECT replace(replace(replace(replace(replace(content,
+    '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), '"', '&quot;'), '''', '&#39;');
+$$
+LANGUAGE sql
+IMMUTABLE;
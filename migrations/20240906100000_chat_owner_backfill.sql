@@ -0,0 +1,45 @@
+-- Add migration script here
+-- chats created before chat owners were recorded are owned by the super user,
+-- who is no member. They go to the workspace owner when they are a member,
+-- otherwise to the earliest member
+WITH heirs AS (
+  SELECT DISTINCT ON (cm.chat_id)
+    cm.chat_id,
+    cm.user_id
+  FROM
+    chat_members cm
+    JOIN chats c ON c.id = cm.chat_id
+    JOIN workspaces w ON w.id = c.ws_id
+  WHERE
+    NOT EXISTS (
+      SELECT
+        1
+      FROM
+        chat_members o
+      WHERE
+        o.chat_id = c.id
+        AND o.user_id = c.owner_id)
+  ORDER BY
+    cm.chat_id,
+    cm.user_id = w.owner_id DESC,
+    cm.joined_at,
+    cm.user_id)
+UPDATE
+  chats c
+SET
+  owner_id = h.user_id
+FROM
+  heirs h
+WHERE
+  c.id = h.chat_id;
+
+UPDATE
+  chat_members cm
+SET
+  role = 'owner'
+FROM
+  chats c
+WHERE
+  c.id = cm.chat_id
+  AND cm.user_id = c.owner_id
+  AND cm.role <> 'owner';
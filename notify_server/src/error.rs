@@ -10,13 +10,25 @@ pub enum AppError {
 
     #[error("jwt error: {0}")]
     JwtError(#[from] jwt_simple::Error),
+
+    #[error("token revoked: {0}")]
+    TokenRevoked(String),
+
+    #[error("sql error: {0}")]
+    SqlxError(#[from] sqlx::Error),
+
+    #[error("invalid presence: {0}")]
+    InvalidPresence(String),
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
         let status = match &self {
             Self::JwtError(_) => StatusCode::FORBIDDEN,
+            Self::TokenRevoked(_) => StatusCode::FORBIDDEN,
             Self::IoError(_) => StatusCode::CONFLICT,
+            Self::SqlxError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::InvalidPresence(_) => StatusCode::BAD_REQUEST,
         };
 
         (status, Json(serde_json::json!({"error": self.to_string()}))).into_response()
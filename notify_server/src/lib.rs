@@ -1,27 +1,36 @@
 mod config;
 mod error;
 mod notify;
+mod presence;
 mod sse;
+mod users;
+mod ws;
 
 pub use config::*;
 pub use notify::*;
 
 pub use error::AppError;
+pub use presence::{PresenceChange, SetPresence};
+pub use users::{ConnectionGuard, UserMap};
+pub use ws::ClientFrame;
 
 use axum::{
+    extract::State,
+    http::StatusCode,
     middleware::from_fn_with_state,
     response::{Html, IntoResponse},
-    routing::get,
-    Router,
+    routing::{get, post},
+    Json, Router,
 };
-use chat_core::{verriy_token, DecodingKey, TokenVeirfy, User};
-use dashmap::DashMap;
+use chat_core::{verriy_token, DecodingKey, RevocationList, TokenSession, TokenVeirfy, User};
+use sqlx::PgPool;
 use std::{ops::Deref, sync::Arc};
 
+use presence::{set_presence_handler, track_presence};
+use serde::Serialize;
 use sse::sse_handler;
-use tokio::sync::broadcast;
-
-pub type UserMap = Arc<DashMap<u64, broadcast::Sender<Arc<AppEvent>>>>;
+use tokio::sync::mpsc;
+use ws::ws_handler;
 
 #[derive(Clone)]
 pub struct AppState(Arc<AppStateInner>);
@@ -30,17 +39,27 @@ pub struct AppStateInner {
     pub config: AppConfig,
     users: UserMap,
     dk: DecodingKey,
+    pool: PgPool,
+    health: ListenerHealth,
+    typing: TypingLimiter,
+    revocations: RevocationList,
 }
 
 const INDEX_HTML: &str = include_str!("../index.html");
 
 pub fn get_router() -> (Router, AppState) {
     let config = AppConfig::load().expect("Failed to load config");
-    let state = AppState::new(config);
+    let (presence_tx, presence_rx) = mpsc::unbounded_channel();
+    let state = AppState::new(config, presence_tx);
+    tokio::spawn(track_presence(state.clone(), presence_rx));
     let app = Router::new()
         .route("/events", get(sse_handler))
+        .route("/ws", get(ws_handler))
+        .route("/presence", post(set_presence_handler))
         .layer(from_fn_with_state(state.clone(), verriy_token::<AppState>))
         .route("/", get(index_handler))
+        .route("/health", get(health_handler))
+        .route("/metrics", get(metrics_handler))
         .with_state(state.clone());
     (app, state)
 }
@@ -49,10 +68,40 @@ async fn index_handler() -> impl IntoResponse {
     Html(INDEX_HTML)
 }
 
+async fn health_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let status = state.health.status();
+    let code = if status.connected {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (code, Json(status))
+}
+
+#[derive(Debug, Serialize)]
+struct Metrics {
+    online_users: usize,
+    connections: usize,
+}
+
+async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
+    Json(Metrics {
+        online_users: state.users.online_users(),
+        connections: state.users.total_connections(),
+    })
+}
+
 impl TokenVeirfy for AppState {
     type Err = AppError;
-    fn verify(&self, token: &str) -> Result<User, Self::Err> {
-        Ok(self.dk.verify(token)?)
+    fn verify(&self, token: &str) -> Result<(User, TokenSession), Self::Err> {
+        let (user, session) = self.dk.verify(token)?;
+        if self.revocations.is_revoked(&session) {
+            return Err(AppError::TokenRevoked(format!(
+                "session {} of user {}",
+                session.sid, user.id
+            )));
+        }
+        Ok((user, session))
     }
 }
 
@@ -65,9 +114,60 @@ impl Deref for AppState {
 }
 
 impl AppState {
-    pub fn new(config: AppConfig) -> Self {
+    pub fn new(config: AppConfig, presence: mpsc::UnboundedSender<PresenceChange>) -> Self {
         let dk = DecodingKey::load(&config.auth.pk).expect("Failed to load public key");
-        let users = Arc::new(DashMap::new());
-        Self(Arc::new(AppStateInner { config, users, dk }))
+        let users = UserMap::new(presence);
+        let pool = PgPool::connect_lazy(&config.server.db_url).expect("Failed to create db pool");
+        Self(Arc::new(AppStateInner {
+            config,
+            users,
+            dk,
+            pool,
+            health: ListenerHealth::default(),
+            typing: TypingLimiter::default(),
+            revocations: RevocationList::default(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test_util {
+    use super::*;
+    use sqlx::Executor;
+    use sqlx_db_tester::TestPg;
+    use std::path::Path;
+
+    impl AppState {
+        // a state on a fresh database with chat_server's fixtures
+        pub async fn new_for_test() -> anyhow::Result<(TestPg, Self)> {
+            let config = AppConfig::load()?;
+            let post = config.server.db_url.rfind('/').expect("invalid db_url");
+            let server_url = config.server.db_url[..post].to_string();
+            let tdb = TestPg::new(server_url, Path::new("../migrations"));
+            let pool = tdb.get_pool().await;
+
+            let sql = include_str!("../../chat_server/fixtures/test.sql").split(';');
+            let mut tx = pool.begin().await?;
+            for s in sql {
+                if s.trim().is_empty() {
+                    continue;
+                }
+                tx.execute(s).await?;
+            }
+            tx.commit().await?;
+
+            let dk = DecodingKey::load(&config.auth.pk)?;
+            let (presence, _) = mpsc::unbounded_channel();
+            let state = Self(Arc::new(AppStateInner {
+                config,
+                users: UserMap::new(presence),
+                dk,
+                pool,
+                health: ListenerHealth::default(),
+                typing: TypingLimiter::default(),
+                revocations: RevocationList::default(),
+            }));
+            Ok((tdb, state))
+        }
     }
 }
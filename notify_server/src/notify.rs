@@ -1,13 +1,33 @@
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::HashSet,
+    fmt,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
 
-use chat_core::{Chat, Message};
+use chat_core::{
+    parse_notification, Chat, ChatMessageCreated, ChatMessageUpdated, ChatOp, ChatRead,
+    ChatReadUpdated, ChatTyping, ChatUpdated, Message, MessageReactionChanged, PresenceStatus,
+    Reaction, CHAT_MESSAGE_CREATED, CHAT_MESSAGE_DELETED, CHAT_MESSAGE_UPDATED, CHAT_READ,
+    CHAT_TYPING, CHAT_UPDATED, MESSAGE_REACTION_CHANGED, NOTIFICATION_VERSION, TOKEN_REVOKED,
+};
+use chrono::{DateTime, Utc};
+use dashmap::{mapref::entry::Entry, DashMap};
 use serde::{Deserialize, Serialize};
-use sqlx::postgres::PgListener;
-use tokio_stream::StreamExt;
+use sqlx::{postgres::PgListener, FromRow};
 use tracing::{info, warn};
 
 use crate::AppState;
 
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// max number of messages replayed to a client resuming with Last-Event-ID
+const MAX_MISSED_MESSAGES: i64 = 500;
+// a user sends at most one typing event per interval, whatever the chat
+const TYPING_INTERVAL: Duration = Duration::from_secs(1);
+const TYPING_LIMITER_CAPACITY: usize = 1024;
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum AppEvent {
@@ -15,6 +35,34 @@ pub enum AppEvent {
     AddToChat(Chat),
     RemoveFromChat(Chat),
     NewMessage(Message),
+    MessageUpdated(Message),
+    MessageDeleted(Message),
+    Typing {
+        chat_id: i64,
+        user_id: i64,
+    },
+    PresenceChanged {
+        user_id: i64,
+        status: PresenceStatus,
+    },
+    ReadReceipt(ChatRead),
+    ReactionChanged {
+        chat_id: i64,
+        op: ChatOp,
+        reaction: Reaction,
+    },
+    // events were lost, the client has to reload its chats and messages
+    Resync {
+        reason: String,
+    },
+}
+
+// stable SSE event id. Only NewChat and NewMessage carry one, the other
+// events keep the client's last id so resuming never skips a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventId {
+    Chat(i64),
+    Message(i64),
 }
 
 #[derive(Debug)]
@@ -24,97 +72,739 @@ pub struct Notification {
     event: Arc<AppEvent>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct ChatUpdated {
-    op: String,
-    old: Option<Chat>,
-    new: Option<Chat>,
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ListenerStatus {
+    pub connected: bool,
+    pub reconnects: u64,
+    pub last_message_id: i64,
+    pub last_error: Option<String>,
+    // db time of the last successful (re)connect, chat changes after it are caught up
+    pub synced_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct ChatMessageCreated {
-    chat: Chat,
+#[derive(Debug, Clone, Default)]
+pub struct ListenerHealth(Arc<RwLock<ListenerStatus>>);
+
+// rate limit of typing events per user, so typing in many chats at once
+// can't flood the members' broadcast channels either
+#[derive(Debug, Default)]
+pub struct TypingLimiter(DashMap<u64, Instant>);
+
+#[derive(Debug, FromRow)]
+struct MessageRow {
+    #[sqlx(flatten)]
     message: Message,
+    members: Vec<i64>,
 }
 
+// spawn a supervised listener, it reconnects with backoff and catches up on
+// the messages and chat changes it missed while disconnected
 pub async fn setup_pg_listener(state: AppState) -> anyhow::Result<()> {
+    tokio::spawn(async move {
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            match run_listener(&state).await {
+                Ok(()) => warn!("pg listener connection lost"),
+                Err(e) => {
+                    warn!("pg listener failed: {}", e);
+                    state.health.update(|s| s.last_error = Some(e.to_string()));
+                }
+            }
+
+            // a connection that came up resets the backoff
+            if state.health.status().connected {
+                backoff = MIN_BACKOFF;
+            }
+            state.health.update(|s| s.connected = false);
+            info!("reconnecting pg listener in {:?}", backoff);
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+            state.health.update(|s| s.reconnects += 1);
+        }
+    });
+
+    Ok(())
+}
+
+async fn run_listener(state: &AppState) -> anyhow::Result<()> {
     let mut listener = PgListener::connect(&state.config.server.db_url).await?;
-    listener.listen("chat_updated").await?;
-    listener.listen("chat_message_created").await?;
+    listener.listen(CHAT_UPDATED).await?;
+    listener.listen(CHAT_MESSAGE_CREATED).await?;
+    listener.listen(CHAT_MESSAGE_UPDATED).await?;
+    listener.listen(CHAT_MESSAGE_DELETED).await?;
+    listener.listen(CHAT_TYPING).await?;
+    listener.listen(CHAT_READ).await?;
+    listener.listen(MESSAGE_REACTION_CHANGED).await?;
+    listener.listen(TOKEN_REVOKED).await?;
 
-    let mut stream = listener.into_stream();
+    // only catch up after LISTEN, so nothing falls between catch-up and the stream
+    let (synced_at,): (DateTime<Utc>,) = sqlx::query_as("SELECT now()")
+        .fetch_one(&state.pool)
+        .await?;
+    let revocations = state.revocations.load(&state.pool).await?;
+    info!("{} token revocations loaded", revocations);
+    let status = state.health.status();
+    let replayed = match status.synced_at {
+        Some(since) => {
+            let replayed = catch_up(state, status.last_message_id, since).await?;
+            // deleted chats and removed members can't be replayed, the rows are gone
+            state
+                .users
+                .broadcast(resync("notification listener reconnected".to_string()));
+            replayed
+        }
+        None => {
+            let (last_id,): (i64,) = sqlx::query_as("SELECT COALESCE(MAX(id), 0) FROM messages")
+                .fetch_one(&state.pool)
+                .await?;
+            state.health.update(|s| s.last_message_id = last_id);
+            HashSet::new()
+        }
+    };
+    state.health.update(|s| {
+        s.connected = true;
+        s.synced_at = Some(synced_at);
+    });
+    info!("pg listener connected");
 
-    tokio::spawn(async move {
-        while let Some(Ok(notif)) = stream.next().await {
-            info!("notification: {:?}", notif);
-            let notification = Notification::load(notif.channel(), notif.payload())?;
-            let _users = &state.users;
-            for user_id in notification.user_ids {
-                if let Some(tx) = state.users.get(&user_id) {
-                    info!("sending notification to user {}", user_id);
-                    if let Err(e) = tx.send(notification.event.clone()) {
-                        warn!("Failed to send notification to user {}: {}", user_id, e);
+    // try_recv returns None when the connection is lost. Don't let PgListener
+    // reconnect silently, we'd miss the notifications sent in between
+    while let Some(notif) = listener.try_recv().await? {
+        info!("notification: {:?}", notif);
+        if notif.channel() == TOKEN_REVOKED {
+            if let Err(e) = state.revocations.apply(notif.payload()) {
+                warn!("Failed to load token revocation: {}", e);
+            }
+            continue;
+        }
+        // a bad payload shouldn't stop the listener
+        let notifications = match Notification::load(notif.channel(), notif.payload()) {
+            Ok(notifications) => notifications,
+            Err(e) => {
+                warn!(
+                    "Failed to load notification from {}: {}",
+                    notif.channel(),
+                    e
+                );
+                continue;
+            }
+        };
+        for notification in notifications {
+            match notification.event.as_ref() {
+                AppEvent::NewMessage(msg) => {
+                    if replayed.contains(&msg.id) {
+                        continue;
                     }
+                    state
+                        .health
+                        .update(|s| s.last_message_id = s.last_message_id.max(msg.id));
                 }
+                AppEvent::Typing { user_id, .. } if !state.typing.allow(*user_id as u64) => {
+                    continue
+                }
+                _ => {}
             }
+            notification.send(state);
         }
+    }
 
-        Ok::<_, anyhow::Error>(())
-    });
+    Ok(())
+}
+
+// replay what happened while the listener was down. Deleted chats can't be
+// recovered since the rows are gone, connected clients are told to resync
+async fn catch_up(
+    state: &AppState,
+    last_message_id: i64,
+    since: DateTime<Utc>,
+) -> anyhow::Result<HashSet<i64>> {
+    let chats: Vec<Chat> = sqlx::query_as(
+        r#"
+        SELECT id, ws_id, owner_id, name, type, chat_member_ids(id) AS members, created_at
+        FROM chats
+        WHERE updated_at > $1
+        ORDER BY id
+        "#,
+    )
+    .bind(since)
+    .fetch_all(&state.pool)
+    .await?;
+    info!("catching up {} chats changed since {}", chats.len(), since);
+    for chat in chats {
+        let user_ids = chat.members.iter().map(|v| *v as u64).collect();
+        let event = if chat.created_at > since {
+            AppEvent::NewChat(chat)
+        } else {
+            AppEvent::AddToChat(chat)
+        };
+        Notification {
+            user_ids,
+            event: Arc::new(event),
+        }
+        .send(state);
+    }
 
+    let rows: Vec<MessageRow> = sqlx::query_as(
+        r#"
+        SELECT m.id, m.chat_id, m.sender_id, m.content, m.files, m.created_at,
+            m.edited_at, m.deleted_at, m.reply_to, m.thread_root_id,
+            chat_member_ids(m.chat_id) AS members
+        FROM messages m
+        WHERE m.id > $1 OR m.edited_at > $2 OR m.deleted_at > $2
+        ORDER BY m.id
+        "#,
+    )
+    .bind(last_message_id)
+    .bind(since)
+    .fetch_all(&state.pool)
+    .await?;
+    info!(
+        "catching up {} messages after {}",
+        rows.len(),
+        last_message_id
+    );
+    let mut replayed = HashSet::new();
+    for row in rows {
+        // an old message edited or deleted while we were away
+        if row.message.id <= last_message_id {
+            Notification::from(ChatMessageUpdated {
+                version: NOTIFICATION_VERSION,
+                message: row.message,
+                members: row.members,
+            })
+            .send(state);
+            continue;
+        }
+        replayed.insert(row.message.id);
+        state
+            .health
+            .update(|s| s.last_message_id = s.last_message_id.max(row.message.id));
+        Notification::from(ChatMessageCreated {
+            version: NOTIFICATION_VERSION,
+            thread_root_id: row.message.thread_root_id,
+            message: row.message,
+            members: row.members,
+        })
+        .send(state);
+    }
+    Ok(replayed)
+}
+
+// load the NewChat/NewMessage events a user missed after `last_id`. When
+// they can't all be replayed the client gets a single Resync instead
+async fn load_missed_events(
+    state: &AppState,
+    user_id: u64,
+    last_id: EventId,
+) -> anyhow::Result<Vec<Arc<AppEvent>>> {
+    // when the last event happened. For a chat that's when the user joined it,
+    // chats joined after it are new to the client whatever their id
+    let since: Option<(DateTime<Utc>,)> = match last_id {
+        EventId::Chat(id) => {
+            sqlx::query_as(
+                r#"
+                SELECT COALESCE(cm.joined_at, c.created_at)
+                FROM chats c
+                LEFT JOIN chat_members cm ON cm.chat_id = c.id AND cm.user_id = $2
+                WHERE c.id = $1
+                "#,
+            )
+            .bind(id)
+            .bind(user_id as i64)
+            .fetch_optional(&state.pool)
+            .await?
+        }
+        EventId::Message(id) => {
+            sqlx::query_as("SELECT created_at FROM messages WHERE id = $1")
+                .bind(id)
+                .fetch_optional(&state.pool)
+                .await?
+        }
+    };
+
+    // the last event's chat was deleted, there's no telling what came after it
+    let Some((since,)) = since else {
+        return Ok(vec![resync(format!("last event {last_id} not found"))]);
+    };
+
+    let chats: Vec<Chat> = sqlx::query_as(
+        r#"
+        SELECT c.id, c.ws_id, c.owner_id, c.name, c.type, chat_member_ids(c.id) AS members,
+            c.created_at
+        FROM chats c
+        JOIN chat_members cm ON cm.chat_id = c.id AND cm.user_id = $1
+        WHERE cm.joined_at > $2
+        ORDER BY cm.joined_at, c.id
+        "#,
+    )
+    .bind(user_id as i64)
+    .bind(since)
+    .fetch_all(&state.pool)
+    .await?;
+
+    // one more than we replay, to tell whether the replay would be cut off
+    let message_cond = match last_id {
+        EventId::Chat(_) => "m.created_at > $2",
+        EventId::Message(_) => "m.id > $2",
+    };
+    let sql = format!(
+        r#"
+        SELECT m.id, m.chat_id, m.sender_id, m.content, m.files, m.created_at,
+            m.edited_at, m.deleted_at, m.reply_to, m.thread_root_id
+        FROM messages m
+        JOIN chat_members cm ON cm.chat_id = m.chat_id AND cm.user_id = $1
+        WHERE {message_cond}
+        ORDER BY m.id
+        LIMIT $3
+        "#
+    );
+    let query = sqlx::query_as(&sql).bind(user_id as i64);
+    let query = match last_id {
+        EventId::Chat(_) => query.bind(since),
+        EventId::Message(id) => query.bind(id),
+    };
+    let messages: Vec<Message> = query
+        .bind(MAX_MISSED_MESSAGES + 1)
+        .fetch_all(&state.pool)
+        .await?;
+    if messages.len() as i64 > MAX_MISSED_MESSAGES {
+        return Ok(vec![resync(format!(
+            "more than {MAX_MISSED_MESSAGES} messages missed"
+        ))]);
+    }
+
+    let events = chats
+        .into_iter()
+        .map(AppEvent::NewChat)
+        .chain(messages.into_iter().map(AppEvent::NewMessage))
+        .map(Arc::new)
+        .collect();
+    Ok(events)
+}
+
+// the events to replay to a client resuming after `last_id`, with their ids
+// so they are skipped when they show up again on the live stream. Call it
+// after subscribing, so nothing falls between the replay and the live events
+pub(crate) async fn replay_missed_events(
+    state: &AppState,
+    user_id: u64,
+    last_id: Option<EventId>,
+) -> (Vec<Arc<AppEvent>>, HashSet<EventId>) {
+    let missed = match last_id {
+        Some(last_id) => match load_missed_events(state, user_id, last_id).await {
+            Ok(events) => {
+                info!("replaying {} events to user {}", events.len(), user_id);
+                events
+            }
+            Err(e) => {
+                warn!("Failed to load missed events for user {}: {}", user_id, e);
+                vec![]
+            }
+        },
+        None => vec![],
+    };
+    let replayed = missed.iter().filter_map(|v| v.id()).collect();
+    (missed, replayed)
+}
+
+pub(crate) fn resync(reason: String) -> Arc<AppEvent> {
+    Arc::new(AppEvent::Resync { reason })
+}
+
+// tell the other members of a chat that the user is typing
+pub(crate) async fn relay_typing(
+    state: &AppState,
+    chat_id: i64,
+    user_id: i64,
+) -> anyhow::Result<()> {
+    // check the limit first, so a noisy client doesn't hit the db either
+    if !state.typing.allow(user_id as u64) {
+        return Ok(());
+    }
+    let members: Option<(Vec<i64>,)> = sqlx::query_as(
+        "SELECT chat_member_ids($1) FROM chat_members WHERE chat_id = $1 AND user_id = $2",
+    )
+    .bind(chat_id)
+    .bind(user_id)
+    .fetch_optional(&state.pool)
+    .await?;
+    let Some((members,)) = members else {
+        anyhow::bail!("user {} is not a member of chat {}", user_id, chat_id);
+    };
+    Notification::from(ChatTyping {
+        version: NOTIFICATION_VERSION,
+        chat_id,
+        user_id,
+        members,
+    })
+    .send(state);
     Ok(())
 }
 
+impl AppEvent {
+    pub fn id(&self) -> Option<EventId> {
+        match self {
+            AppEvent::NewChat(chat) => Some(EventId::Chat(chat.id)),
+            AppEvent::NewMessage(msg) => Some(EventId::Message(msg.id)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for EventId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventId::Chat(id) => write!(f, "c:{id}"),
+            EventId::Message(id) => write!(f, "m:{id}"),
+        }
+    }
+}
+
+impl FromStr for EventId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("c", id)) => Ok(EventId::Chat(id.parse()?)),
+            Some(("m", id)) => Ok(EventId::Message(id.parse()?)),
+            _ => Err(anyhow::anyhow!("Invalid event id: {}", s)),
+        }
+    }
+}
+
 impl Notification {
-    fn load(r#type: &str, paylod: &str) -> anyhow::Result<Self> {
-        match r#type {
-            "chat_updated" => {
-                let payload: ChatUpdated = serde_json::from_str(paylod)?;
+    pub(crate) fn new(user_ids: impl IntoIterator<Item = u64>, event: Arc<AppEvent>) -> Self {
+        Self {
+            user_ids: user_ids.into_iter().collect(),
+            event,
+        }
+    }
+
+    // a chat update can notify members and removed members differently, so
+    // one payload may turn into several notifications
+    fn load(r#type: &str, paylod: &str) -> anyhow::Result<Vec<Self>> {
+        let notification = match r#type {
+            CHAT_UPDATED => {
+                let payload: ChatUpdated = parse_notification(paylod)?;
                 info!("chat_updated. payload: {:?}", payload);
-                let user_ids =
-                    get_affected_cbat_user_ids(payload.old.as_ref(), payload.new.as_ref());
-                let event = match payload.op.as_str() {
-                    "INSERT" => AppEvent::NewChat(payload.new.expect("new should exist")),
-                    "UPDATE" => AppEvent::AddToChat(payload.new.expect("new should exist")),
-                    "DELETE" => AppEvent::RemoveFromChat(payload.old.expect("old should exist")),
-                    _ => return Err(anyhow::anyhow!("Invalid op: {}", payload.op)),
-                };
-                Ok(Self {
-                    user_ids,
-                    event: Arc::new(event),
-                })
+                return chat_updated_notifications(payload);
             }
-            "chat_message_created" => {
-                let payload: ChatMessageCreated = serde_json::from_str(paylod)?;
+            CHAT_MESSAGE_CREATED => {
+                let payload: ChatMessageCreated = parse_notification(paylod)?;
                 info!("chat_message_created. payload: {:?}", payload);
-                let user_ids = payload.chat.members.iter().map(|v| *v as u64).collect();
-                Ok(Self {
-                    user_ids,
-                    event: Arc::new(AppEvent::NewMessage(payload.message)),
-                })
+                payload.into()
+            }
+            CHAT_MESSAGE_UPDATED => {
+                let payload: ChatMessageUpdated = parse_notification(paylod)?;
+                info!("chat_message_updated. payload: {:?}", payload);
+                payload.into()
+            }
+            CHAT_MESSAGE_DELETED => {
+                let payload: ChatMessageUpdated = parse_notification(paylod)?;
+                info!("chat_message_deleted. payload: {:?}", payload);
+                payload.into()
+            }
+            CHAT_TYPING => {
+                let payload: ChatTyping = parse_notification(paylod)?;
+                payload.into()
+            }
+            MESSAGE_REACTION_CHANGED => {
+                let payload: MessageReactionChanged = parse_notification(paylod)?;
+                info!("message_reaction_changed. payload: {:?}", payload);
+                payload.into()
+            }
+            CHAT_READ => {
+                let payload: ChatReadUpdated = parse_notification(paylod)?;
+                info!("chat_read. payload: {:?}", payload);
+                payload.into()
             }
             _ => {
                 info!("Invalid type: {}", r#type);
-                Err(anyhow::anyhow!("Invalid type: {}", r#type))
+                return Err(anyhow::anyhow!("Invalid type: {}", r#type));
+            }
+        };
+        Ok(vec![notification])
+    }
+
+    pub(crate) fn send(self, state: &AppState) {
+        for user_id in self.user_ids {
+            state.users.send(user_id, self.event.clone());
+        }
+    }
+}
+
+impl From<ChatMessageCreated> for Notification {
+    fn from(payload: ChatMessageCreated) -> Self {
+        Self {
+            user_ids: payload.members.iter().map(|v| *v as u64).collect(),
+            event: Arc::new(AppEvent::NewMessage(payload.message)),
+        }
+    }
+}
+
+// a tombstone can't be edited any more, so deleted_at tells the two apart
+impl From<ChatMessageUpdated> for Notification {
+    fn from(payload: ChatMessageUpdated) -> Self {
+        let event = if payload.message.deleted_at.is_some() {
+            AppEvent::MessageDeleted(payload.message)
+        } else {
+            AppEvent::MessageUpdated(payload.message)
+        };
+        Self {
+            user_ids: payload.members.iter().map(|v| *v as u64).collect(),
+            event: Arc::new(event),
+        }
+    }
+}
+
+// typing events go to the other members only
+impl From<ChatTyping> for Notification {
+    fn from(payload: ChatTyping) -> Self {
+        let (chat_id, user_id) = (payload.chat_id, payload.user_id);
+        Self {
+            user_ids: payload
+                .members
+                .into_iter()
+                .filter(|id| *id != user_id)
+                .map(|id| id as u64)
+                .collect(),
+            event: Arc::new(AppEvent::Typing { chat_id, user_id }),
+        }
+    }
+}
+
+impl From<MessageReactionChanged> for Notification {
+    fn from(payload: MessageReactionChanged) -> Self {
+        Self {
+            user_ids: payload.members.iter().map(|v| *v as u64).collect(),
+            event: Arc::new(AppEvent::ReactionChanged {
+                chat_id: payload.chat_id,
+                op: payload.op,
+                reaction: payload.reaction,
+            }),
+        }
+    }
+}
+
+// the reader already knows, only tell the other members
+impl From<ChatReadUpdated> for Notification {
+    fn from(payload: ChatReadUpdated) -> Self {
+        let user_id = payload.read.user_id;
+        Self {
+            user_ids: payload
+                .members
+                .into_iter()
+                .filter(|id| *id != user_id)
+                .map(|id| id as u64)
+                .collect(),
+            event: Arc::new(AppEvent::ReadReceipt(payload.read)),
+        }
+    }
+}
+
+impl TypingLimiter {
+    pub fn allow(&self, user_id: u64) -> bool {
+        let now = Instant::now();
+        if self.0.len() > TYPING_LIMITER_CAPACITY {
+            self.0
+                .retain(|_, last| now.duration_since(*last) < TYPING_INTERVAL);
+        }
+        match self.0.entry(user_id) {
+            Entry::Occupied(mut entry) => {
+                if now.duration_since(*entry.get()) < TYPING_INTERVAL {
+                    return false;
+                }
+                entry.insert(now);
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(now);
             }
         }
+        true
+    }
+}
+
+impl ListenerHealth {
+    pub fn status(&self) -> ListenerStatus {
+        self.0
+            .read()
+            .expect("listener health lock poisoned")
+            .clone()
+    }
+
+    fn update(&self, f: impl FnOnce(&mut ListenerStatus)) {
+        f(&mut self.0.write().expect("listener health lock poisoned"));
     }
 }
 
-fn get_affected_cbat_user_ids(old: Option<&Chat>, new: Option<&Chat>) -> HashSet<u64> {
-    match (old, new) {
-        (Some(old), Some(new)) => {
-            // diff old/new members, if identical, no need to notify, otherwise notify the union of both
-            let old_members: HashSet<_> = old.members.iter().map(|v| *v as u64).collect();
-            let new_members: HashSet<_> = new.members.iter().map(|v| *v as u64).collect();
-            if old_members == new_members {
-                HashSet::new()
-            } else {
-                old_members.union(&new_members).copied().collect()
+// members are added to the chat or told about its change, members removed
+// by this change are removed from it. A deleted chat has no current members,
+// its old members are removed
+fn chat_updated_notifications(payload: ChatUpdated) -> anyhow::Result<Vec<Notification>> {
+    let ids = |members: &[i64]| members.iter().map(|v| *v as u64).collect::<Vec<_>>();
+    let notifications = match (payload.op, payload.old, payload.new) {
+        (ChatOp::Insert, _, Some(new)) => {
+            vec![Notification::new(
+                ids(&new.members),
+                Arc::new(AppEvent::NewChat(new)),
+            )]
+        }
+        (ChatOp::Update, _, Some(new)) => {
+            let mut notifications = vec![Notification::new(
+                ids(&new.members),
+                Arc::new(AppEvent::AddToChat(new.clone())),
+            )];
+            if !payload.removed.is_empty() {
+                notifications.push(Notification::new(
+                    ids(&payload.removed),
+                    Arc::new(AppEvent::RemoveFromChat(new)),
+                ));
             }
+            notifications
+        }
+        (ChatOp::Delete, Some(old), _) => {
+            let user_ids = ids(&old.members).into_iter().chain(ids(&payload.removed));
+            vec![Notification::new(
+                user_ids,
+                Arc::new(AppEvent::RemoveFromChat(old)),
+            )]
+        }
+        (op, _, _) => return Err(anyhow::anyhow!("Invalid chat for op: {:?}", op)),
+    };
+    Ok(notifications)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the users notified and the kind of event each notification carries
+    fn summarize(notifications: &[Notification]) -> Vec<(HashSet<u64>, &'static str)> {
+        notifications
+            .iter()
+            .map(|n| {
+                let kind = match n.event.as_ref() {
+                    AppEvent::NewChat(_) => "NewChat",
+                    AppEvent::AddToChat(_) => "AddToChat",
+                    AppEvent::RemoveFromChat(_) => "RemoveFromChat",
+                    _ => "Other",
+                };
+                (n.user_ids.clone(), kind)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn deleted_chat_should_remove_old_members() -> anyhow::Result<()> {
+        let chat = Chat {
+            id: 1,
+            ws_id: 1,
+            owner_id: 1,
+            name: Some("general".to_string()),
+            r#type: chat_core::ChatType::PublicChannel,
+            members: vec![1, 2, 3],
+            created_at: Utc::now(),
+        };
+        let payload = ChatUpdated {
+            version: NOTIFICATION_VERSION,
+            op: ChatOp::Delete,
+            old: Some(chat),
+            new: None,
+            added: vec![],
+            removed: vec![1, 2, 3],
+        };
+        let notifications = chat_updated_notifications(payload)?;
+        assert_eq!(
+            summarize(&notifications),
+            vec![(HashSet::from([1, 2, 3]), "RemoveFromChat")]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn removed_members_should_get_remove_from_chat() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut listener = PgListener::connect_with(&state.pool).await?;
+        listener.listen(CHAT_UPDATED).await?;
+
+        // chat 2 has users 1, 2 and 3
+        sqlx::query("DELETE FROM chat_members WHERE chat_id = 2 AND user_id = 3")
+            .execute(&state.pool)
+            .await?;
+        let notif = listener.recv().await?;
+        let notifications = Notification::load(notif.channel(), notif.payload())?;
+        assert_eq!(
+            summarize(&notifications),
+            vec![
+                (HashSet::from([1, 2]), "AddToChat"),
+                (HashSet::from([3]), "RemoveFromChat"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn event_id_should_round_trip() -> anyhow::Result<()> {
+        for id in [EventId::Chat(1), EventId::Message(42)] {
+            assert_eq!(id.to_string().parse::<EventId>()?, id);
         }
-        (Some(old), None) => old.members.iter().map(|v| *v as u64).collect(),
-        (None, Some(new)) => new.members.iter().map(|v| *v as u64).collect(),
-        (None, None) => HashSet::new(),
+        assert_eq!(EventId::Message(42).to_string(), "m:42");
+        assert_eq!("c:7".parse::<EventId>()?, EventId::Chat(7));
+        assert!("42".parse::<EventId>().is_err());
+        assert!("x:42".parse::<EventId>().is_err());
+        assert!("m:abc".parse::<EventId>().is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn load_missed_events_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // user 1 is in every chat of the fixtures, with messages 1..=10
+        let events = load_missed_events(&state, 1, EventId::Message(5)).await?;
+        let ids: Vec<_> = events.iter().filter_map(|v| v.id()).collect();
+        assert_eq!(ids, (6..=10).map(EventId::Message).collect::<Vec<_>>());
+        assert!(load_missed_events(&state, 1, EventId::Message(10))
+            .await?
+            .is_empty());
+
+        // user 3 is added to chat 3 after seeing chat 4, it's new to them
+        // even though its id is lower
+        assert!(load_missed_events(&state, 3, EventId::Chat(4))
+            .await?
+            .is_empty());
+        sqlx::query("INSERT INTO chat_members (chat_id, user_id) VALUES (3, 3)")
+            .execute(&state.pool)
+            .await?;
+        let events = load_missed_events(&state, 3, EventId::Chat(4)).await?;
+        let ids: Vec<_> = events.iter().filter_map(|v| v.id()).collect();
+        assert_eq!(ids, vec![EventId::Chat(3)]);
+
+        // the anchor is gone
+        let events = load_missed_events(&state, 1, EventId::Message(1000)).await?;
+        assert!(matches!(events[..], [ref v] if matches!(**v, AppEvent::Resync { .. })));
+        let events = load_missed_events(&state, 1, EventId::Chat(1000)).await?;
+        assert!(matches!(events[..], [ref v] if matches!(**v, AppEvent::Resync { .. })));
+
+        // too many to replay
+        sqlx::query(
+            "INSERT INTO messages (chat_id, sender_id, content) SELECT 1, 1, 'hi' FROM generate_series(1, $1)",
+        )
+        .bind(MAX_MISSED_MESSAGES as i32)
+        .execute(&state.pool)
+        .await?;
+        let events = load_missed_events(&state, 1, EventId::Message(10)).await?;
+        assert_eq!(events.len() as i64, MAX_MISSED_MESSAGES);
+        let events = load_missed_events(&state, 1, EventId::Message(9)).await?;
+        assert!(matches!(events[..], [ref v] if matches!(**v, AppEvent::Resync { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn typing_limiter_should_work() {
+        let limiter = TypingLimiter::default();
+        assert!(limiter.allow(1));
+        assert!(!limiter.allow(1));
+        // other users are not affected
+        assert!(limiter.allow(2));
     }
 }
@@ -0,0 +1,165 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension, Json};
+use chat_core::{PresenceStatus, User};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::{AppError, AppEvent, AppState, Notification};
+
+#[derive(Debug, Clone, Copy)]
+pub struct PresenceChange {
+    pub user_id: u64,
+    pub status: PresenceStatus,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetPresence {
+    pub status: PresenceStatus,
+}
+
+pub(crate) async fn set_presence_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<SetPresence>,
+) -> Result<impl IntoResponse, AppError> {
+    let user_id = user.id as u64;
+    if input.status == PresenceStatus::Offline {
+        return Err(AppError::InvalidPresence(
+            "offline is set when the last connection closes".to_string(),
+        ));
+    }
+    if !state.users.set_status(user_id, input.status) {
+        return Err(AppError::InvalidPresence(format!(
+            "user {user_id} has no live connection"
+        )));
+    }
+    Ok(StatusCode::ACCEPTED)
+}
+
+// single writer of user_presence, so changes of a user are applied in order
+pub(crate) async fn track_presence(
+    state: AppState,
+    mut rx: mpsc::UnboundedReceiver<PresenceChange>,
+) {
+    // nobody is connected to a fresh server
+    if let Err(e) = sqlx::query("UPDATE user_presence SET status = 'offline'")
+        .execute(&state.pool)
+        .await
+    {
+        warn!("Failed to reset presence: {}", e);
+    }
+
+    while let Some(change) = rx.recv().await {
+        if let Err(e) = set_presence(&state, change).await {
+            warn!("Failed to set presence of user {}: {}", change.user_id, e);
+        }
+    }
+}
+
+async fn set_presence(state: &AppState, change: PresenceChange) -> Result<(), AppError> {
+    let user_id = change.user_id as i64;
+    let updated: Option<(i64,)> = sqlx::query_as(
+        r#"
+        INSERT INTO user_presence (user_id, status)
+        VALUES ($1, $2)
+        ON CONFLICT (user_id) DO UPDATE
+        SET status = EXCLUDED.status, updated_at = CURRENT_TIMESTAMP
+        WHERE user_presence.status <> EXCLUDED.status
+        RETURNING user_id
+        "#,
+    )
+    .bind(user_id)
+    .bind(change.status)
+    .fetch_optional(&state.pool)
+    .await?;
+    if updated.is_none() {
+        return Ok(());
+    }
+    info!("user {} is {:?}", user_id, change.status);
+
+    // everyone sharing a chat with the user
+    let user_ids: Vec<(i64,)> = sqlx::query_as(
+        r#"
+        SELECT DISTINCT cm.user_id
+        FROM chat_members cm
+        JOIN chat_members me ON me.chat_id = cm.chat_id
+        WHERE me.user_id = $1
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(&state.pool)
+    .await?;
+    let event = AppEvent::PresenceChanged {
+        user_id,
+        status: change.status,
+    };
+    Notification::new(
+        user_ids
+            .into_iter()
+            .filter(|(id,)| *id != user_id)
+            .map(|(id,)| id as u64),
+        Arc::new(event),
+    )
+    .send(state);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UserMap;
+
+    #[test]
+    fn presence_changes_should_follow_connections() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let users = UserMap::new(tx);
+        // nobody to set away without a connection
+        assert!(!users.set_status(1, PresenceStatus::Away));
+
+        let (guard, _rx) = users.subscribe(1);
+        assert!(users.set_status(1, PresenceStatus::Away));
+        drop(guard);
+        assert!(!users.set_status(1, PresenceStatus::Online));
+
+        let changes: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|change| (change.user_id, change.status))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (1, PresenceStatus::Online),
+                (1, PresenceStatus::Away),
+                (1, PresenceStatus::Offline),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn set_presence_should_notify_chat_members() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // user 3 shares chats with user 1, but isn't told about itself
+        let (_g1, mut rx1) = state.users.subscribe(1);
+        let (_g3, mut rx3) = state.users.subscribe(3);
+        let change = PresenceChange {
+            user_id: 3,
+            status: PresenceStatus::Away,
+        };
+        set_presence(&state, change).await?;
+        let event = rx1.try_recv()?;
+        assert!(matches!(
+            *event,
+            AppEvent::PresenceChanged {
+                user_id: 3,
+                status: PresenceStatus::Away
+            }
+        ));
+        assert!(rx3.try_recv().is_err());
+
+        // an unchanged status isn't broadcast again
+        set_presence(&state, change).await?;
+        assert!(rx1.try_recv().is_err());
+        Ok(())
+    }
+}
@@ -1,49 +1,54 @@
-use std::{convert::Infallible, time::Duration};
+use std::{convert::Infallible, sync::Arc, time::Duration};
 
 use axum::{
     extract::State,
+    http::HeaderMap,
     response::sse::{Event, Sse},
     Extension,
 };
 use axum_extra::{headers, TypedHeader};
-use chat_core::User;
+use chat_core::{TokenSession, User};
 use futures::Stream;
-use tokio::sync::broadcast;
 use tokio_stream::{wrappers::BroadcastStream, StreamExt};
 use tracing::info;
 
-use crate::{AppEvent, AppState};
+use crate::{replay_missed_events, AppEvent, AppState, EventId};
 
-const CHANNEL_CAPACITY: usize = 256;
+const LAST_EVENT_ID_HEADER: &str = "last-event-id";
 
 pub(crate) async fn sse_handler(
     Extension(user): Extension<User>,
+    Extension(session): Extension<TokenSession>,
     State(state): State<AppState>,
     TypedHeader(user_agent): TypedHeader<headers::UserAgent>,
+    headers: HeaderMap,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
     info!("`{}` connected", user_agent.as_str());
 
     let user_id = user.id as u64;
-    // let user_id = 1;
-    let users = &state.users;
-
-    let rx = if let Some(tx) = users.get(&user_id) {
-        tx.subscribe()
-    } else {
-        let (tx, rx) = broadcast::channel(CHANNEL_CAPACITY);
-        state.users.insert(user_id, tx);
-        rx
-    };
+
+    // subscribe before loading missed events, so nothing falls in between
+    let (guard, rx) = state.users.subscribe(user_id);
     info!("user {} subscribed", user_id);
-    let stream = BroadcastStream::new(rx).filter_map(|v| v.ok()).map(|v| {
-        let name = match v.as_ref() {
-            AppEvent::NewChat(_) => "NewChat",
-            AppEvent::AddToChat(_) => "AddToChat",
-            AppEvent::RemoveFromChat(_) => "RemoveFromChat",
-            AppEvent::NewMessage(_) => "NewMessage",
-        };
-        let v = serde_json::to_string(&v).expect("Failed to serialize event");
-        Ok(Event::default().data(v).event(name))
+
+    let last_id = headers
+        .get(LAST_EVENT_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<EventId>().ok());
+    let (missed, replayed) = replay_missed_events(&state, user_id, last_id).await;
+    let live = BroadcastStream::new(rx)
+        .filter_map(|v| v.ok())
+        .filter(move |v| v.id().is_none_or(|id| !replayed.contains(&id)));
+    let stream = tokio_stream::iter(missed).chain(live).map(move |v| {
+        // the guard lives as long as the stream, axum drops it on disconnect
+        let _ = &guard;
+        Ok(to_sse_event(&v))
+    });
+    // the stream ends once its session is revoked, the client has to sign in again
+    let revocations = state.revocations.clone();
+    let stream = futures::StreamExt::take_until(stream, async move {
+        revocations.revoked(&session).await;
+        info!("session {} revoked, closing stream", session.sid);
     });
 
     Sse::new(stream).keep_alive(
@@ -52,3 +57,25 @@ pub(crate) async fn sse_handler(
             .text("keep-alive-text"),
     )
 }
+
+fn to_sse_event(v: &Arc<AppEvent>) -> Event {
+    let name = match v.as_ref() {
+        AppEvent::NewChat(_) => "NewChat",
+        AppEvent::AddToChat(_) => "AddToChat",
+        AppEvent::RemoveFromChat(_) => "RemoveFromChat",
+        AppEvent::NewMessage(_) => "NewMessage",
+        AppEvent::MessageUpdated(_) => "MessageUpdated",
+        AppEvent::MessageDeleted(_) => "MessageDeleted",
+        AppEvent::Typing { .. } => "Typing",
+        AppEvent::PresenceChanged { .. } => "PresenceChanged",
+        AppEvent::ReadReceipt(_) => "ReadReceipt",
+        AppEvent::ReactionChanged { .. } => "ReactionChanged",
+        AppEvent::Resync { .. } => "Resync",
+    };
+    let data = serde_json::to_string(v).expect("Failed to serialize event");
+    let event = Event::default().data(data).event(name);
+    match v.id() {
+        Some(id) => event.id(id.to_string()),
+        None => event,
+    }
+}
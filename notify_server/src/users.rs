@@ -0,0 +1,175 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use chat_core::PresenceStatus;
+use dashmap::{mapref::entry::Entry, DashMap};
+use tokio::sync::{broadcast, mpsc};
+use tracing::{info, warn};
+
+use crate::{AppEvent, PresenceChange};
+
+const CHANNEL_CAPACITY: usize = 256;
+
+// per-user broadcast channels, an entry lives as long as the user has a live
+// SSE or websocket connection
+#[derive(Debug, Clone, Default)]
+pub struct UserMap {
+    users: Arc<DashMap<u64, UserChannel>>,
+    connections: Arc<AtomicUsize>,
+    // users going online/offline are reported here
+    presence: Option<mpsc::UnboundedSender<PresenceChange>>,
+}
+
+#[derive(Debug)]
+struct UserChannel {
+    tx: broadcast::Sender<Arc<AppEvent>>,
+    connections: usize,
+}
+
+// dropped when the connection closes, the last one removes the user's channel
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    user_id: u64,
+    users: UserMap,
+}
+
+impl UserMap {
+    pub fn new(presence: mpsc::UnboundedSender<PresenceChange>) -> Self {
+        Self {
+            presence: Some(presence),
+            ..Default::default()
+        }
+    }
+
+    pub fn subscribe(&self, user_id: u64) -> (ConnectionGuard, broadcast::Receiver<Arc<AppEvent>>) {
+        let rx = match self.users.entry(user_id) {
+            Entry::Occupied(mut entry) => {
+                let channel = entry.get_mut();
+                channel.connections += 1;
+                channel.tx.subscribe()
+            }
+            Entry::Vacant(entry) => {
+                let (tx, rx) = broadcast::channel(CHANNEL_CAPACITY);
+                // report while holding the entry, so changes of a user stay ordered
+                let _channel = entry.insert(UserChannel { tx, connections: 1 });
+                self.update_presence(user_id, PresenceStatus::Online);
+                rx
+            }
+        };
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        let guard = ConnectionGuard {
+            user_id,
+            users: self.clone(),
+        };
+        (guard, rx)
+    }
+
+    pub fn send(&self, user_id: u64, event: Arc<AppEvent>) {
+        if let Some(channel) = self.users.get(&user_id) {
+            info!("sending notification to user {}", user_id);
+            if let Err(e) = channel.tx.send(event) {
+                warn!("Failed to send notification to user {}: {}", user_id, e);
+            }
+        }
+    }
+
+    // every user with a live connection
+    pub fn broadcast(&self, event: Arc<AppEvent>) {
+        for channel in self.users.iter() {
+            // no receiver left means the connection is closing
+            let _ = channel.tx.send(event.clone());
+        }
+    }
+
+    pub fn update_presence(&self, user_id: u64, status: PresenceStatus) {
+        if let Some(tx) = &self.presence {
+            if let Err(e) = tx.send(PresenceChange { user_id, status }) {
+                warn!("Failed to update presence of user {}: {}", user_id, e);
+            }
+        }
+    }
+
+    // a status set by the user, only while they are connected. The entry is
+    // held, so a closing last connection can't report offline in between
+    pub fn set_status(&self, user_id: u64, status: PresenceStatus) -> bool {
+        match self.users.get(&user_id) {
+            Some(_channel) => {
+                self.update_presence(user_id, status);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // number of live connections of a user
+    pub fn connections(&self, user_id: u64) -> usize {
+        self.users
+            .get(&user_id)
+            .map(|channel| channel.connections)
+            .unwrap_or_default()
+    }
+
+    pub fn online_users(&self) -> usize {
+        self.users.len()
+    }
+
+    pub fn total_connections(&self) -> usize {
+        self.connections.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        if let Entry::Occupied(mut entry) = self.users.users.entry(self.user_id) {
+            let channel = entry.get_mut();
+            channel.connections -= 1;
+            if channel.connections == 0 {
+                self.users
+                    .update_presence(self.user_id, PresenceStatus::Offline);
+                entry.remove();
+                info!("user {} has no connections left", self.user_id);
+            }
+        }
+        self.users.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_map_should_track_connections() {
+        let users = UserMap::default();
+        let (g1, _rx1) = users.subscribe(1);
+        let (g2, _rx2) = users.subscribe(1);
+        let (g3, _rx3) = users.subscribe(2);
+        assert_eq!(users.connections(1), 2);
+        assert_eq!(users.online_users(), 2);
+        assert_eq!(users.total_connections(), 3);
+
+        drop(g1);
+        assert_eq!(users.connections(1), 1);
+        drop(g2);
+        assert_eq!(users.connections(1), 0);
+        assert_eq!(users.online_users(), 1);
+
+        drop(g3);
+        assert_eq!(users.online_users(), 0);
+        assert_eq!(users.total_connections(), 0);
+    }
+
+    #[test]
+    fn broadcast_should_reach_every_user() {
+        let users = UserMap::default();
+        let (_g1, mut rx1) = users.subscribe(1);
+        let (_g2, mut rx2) = users.subscribe(2);
+        users.broadcast(Arc::new(AppEvent::Resync {
+            reason: "test".to_string(),
+        }));
+        assert!(matches!(*rx1.try_recv().unwrap(), AppEvent::Resync { .. }));
+        assert!(matches!(*rx2.try_recv().unwrap(), AppEvent::Resync { .. }));
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::HashSet;
+
+use axum::{
+    extract::{
+        ws::{Message as WsMessage, WebSocket},
+        Query, State, WebSocketUpgrade,
+    },
+    response::IntoResponse,
+    Extension,
+};
+use chat_core::{PresenceStatus, TokenSession, User};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, info, warn};
+
+use crate::{relay_typing, replay_missed_events, resync, AppEvent, AppState, EventId};
+
+// frames sent by the client over `/ws`
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ClientFrame {
+    Typing { chat_id: i64 },
+    // the last event handled, `c:<chat id>` or `m:<message id>`. A client
+    // reconnects with it as `last_event_id` to get the events it missed
+    Ack { event_id: String },
+    // only receive messages and typing events of these chats
+    Subscribe { chat_ids: Vec<i64> },
+    Unsubscribe { chat_ids: Vec<i64> },
+    // online or away, offline is set when the last connection closes
+    SetStatus { status: PresenceStatus },
+}
+
+// the websocket counterpart of SSE's Last-Event-ID, each device resumes
+// from its own last event
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct ResumeParams {
+    last_event_id: Option<String>,
+}
+
+pub(crate) async fn ws_handler(
+    Extension(user): Extension<User>,
+    Extension(session): Extension<TokenSession>,
+    State(state): State<AppState>,
+    Query(params): Query<ResumeParams>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    let last_id = params.last_event_id.and_then(|v| v.parse::<EventId>().ok());
+    ws.on_upgrade(move |socket| handle_socket(socket, user, session, last_id, state))
+}
+
+async fn handle_socket(
+    socket: WebSocket,
+    user: User,
+    session: TokenSession,
+    last_id: Option<EventId>,
+    state: AppState,
+) {
+    let user_id = user.id as u64;
+    let (_guard, mut rx) = state.users.subscribe(user_id);
+    info!("user {} connected via websocket", user_id);
+
+    let (mut sender, mut receiver) = socket.split();
+    let (missed, replayed) = replay_missed_events(&state, user_id, last_id).await;
+    for event in missed {
+        let data = serde_json::to_string(&*event).expect("Failed to serialize event");
+        if sender.send(WsMessage::Text(data)).await.is_err() {
+            return;
+        }
+    }
+
+    let mut chats = HashSet::new();
+    let mut last_ack = None;
+    let revoked = state.revocations.revoked(&session);
+    tokio::pin!(revoked);
+    loop {
+        tokio::select! {
+            _ = &mut revoked => {
+                info!("session {} revoked, closing websocket", session.sid);
+                break;
+            }
+            event = rx.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    // the dropped events are gone, the client has to reload
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("user {} websocket lagged {} events", user_id, n);
+                        resync(format!("{n} events dropped"))
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                if !is_subscribed(&chats, &event)
+                    || event.id().is_some_and(|id| replayed.contains(&id))
+                {
+                    continue;
+                }
+                let data = serde_json::to_string(&*event).expect("Failed to serialize event");
+                if sender.send(WsMessage::Text(data)).await.is_err() {
+                    break;
+                }
+            }
+            msg = receiver.next() => {
+                let text = match msg {
+                    Some(Ok(WsMessage::Text(text))) => text,
+                    Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+                let frame = match serde_json::from_str::<ClientFrame>(&text) {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        warn!("Invalid frame from user {}: {}", user_id, e);
+                        continue;
+                    }
+                };
+                match frame {
+                    ClientFrame::Typing { chat_id } => {
+                        if let Err(e) = relay_typing(&state, chat_id, user.id).await {
+                            warn!("Failed to relay typing from user {}: {}", user_id, e);
+                        }
+                    }
+                    ClientFrame::Ack { event_id } => match event_id.parse::<EventId>() {
+                        Ok(id) => {
+                            debug!("user {} acked {}", user_id, id);
+                            last_ack = Some(id);
+                        }
+                        Err(e) => warn!("Invalid ack from user {}: {}", user_id, e),
+                    },
+                    ClientFrame::Subscribe { chat_ids } => chats.extend(chat_ids),
+                    ClientFrame::Unsubscribe { chat_ids } => {
+                        chats.retain(|id| !chat_ids.contains(id));
+                    }
+                    ClientFrame::SetStatus { status } => {
+                        if status == PresenceStatus::Offline {
+                            warn!("user {} can't set status to offline", user_id);
+                        } else {
+                            state.users.set_status(user_id, status);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    info!("user {} websocket closed, last ack {:?}", user_id, last_ack);
+}
+
+// no subscription means every chat, membership changes are always delivered
+fn is_subscribed(chats: &HashSet<i64>, event: &AppEvent) -> bool {
+    let chat_id = match event {
+        AppEvent::NewMessage(msg)
+        | AppEvent::MessageUpdated(msg)
+        | AppEvent::MessageDeleted(msg) => msg.chat_id,
+        AppEvent::Typing { chat_id, .. } => *chat_id,
+        AppEvent::ReadReceipt(read) => read.chat_id,
+        AppEvent::ReactionChanged { chat_id, .. } => *chat_id,
+        _ => return true,
+    };
+    chats.is_empty() || chats.contains(&chat_id)
+}